@@ -1,30 +1,222 @@
+mod builtin;
+mod diagnostic;
 mod interp;
 mod parser;
 mod scanner;
+mod span;
+mod stack;
 mod type_checker;
 mod typed;
 mod untyped;
 
+use core::fmt;
 use parser::Parser;
-use scanner::Scanner;
-use typed::Expr;
-use typed::Type;
-use untyped::Value;
+use typed::{Expr, Input, TEnv};
+pub use typed::{Type, TypePrinter};
+use untyped::{Env, Value};
 
-pub fn gen_ast(source: &str) -> Expr {
+pub use builtin::{Builtin, BUILTINS};
+pub use diagnostic::{Diagnostic, Label};
+pub use interp::RuntimeError;
+pub use parser::ParseError;
+pub use scanner::{LexError, LexErrorKind, Literal, Scanner, Token, TokenKind, KEYWORDS};
+pub use span::Span;
+pub use type_checker::{Because, TypeError};
+
+/// An error from any stage of the pipeline.
+#[derive(Debug)]
+pub enum Error {
+    Lex(Vec<LexError>),
+    Parse(Vec<ParseError>),
+    Type(TypeError),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Lex(errors) => write_lines(f, errors),
+            Error::Parse(errors) => write_lines(f, errors),
+            Error::Type(error) => write!(f, "{}", error),
+            Error::Runtime(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error {
+    /// The diagnostics of the errors, in the order they were found.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Error::Lex(errors) => errors.iter().map(LexError::diagnostic).collect(),
+            Error::Parse(errors) => errors.iter().map(ParseError::diagnostic).collect(),
+            Error::Type(error) => vec![error.diagnostic()],
+            Error::Runtime(error) => vec![error.diagnostic()],
+        }
+    }
+
+    /// Renders the errors with the lines of `source` they were found in; see `Diagnostic::render`.
+    pub fn render(&self, source: &str, color: bool) -> String {
+        let rendered: Vec<String> = self
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.render(source, color))
+            .collect();
+        rendered.join("\n\n")
+    }
+}
+
+fn write_lines<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            writeln!(f)?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+pub fn gen_ast(source: &str) -> Result<Expr, Error> {
+    let scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan().map_err(Error::Lex)?;
+    let mut parser = Parser::new(tokens);
+    parser.parse().map_err(Error::Parse)
+}
+
+/// Parses `source` as REPL input, the declarations and expressions separated by `;` that `Session` runs.
+pub fn gen_input(source: &str) -> Result<Vec<Input>, Error> {
     let scanner = Scanner::new(source.to_string());
-    let tokens = scanner.scan();
+    let tokens = scanner.scan().map_err(Error::Lex)?;
     let mut parser = Parser::new(tokens);
-    parser.parse().expect("expected expr")
+    parser.parse_input().map_err(Error::Parse)
+}
+
+/// Whether `source` is the beginning of an input that goes on in more lines,
+/// such as one with an unclosed `{` or a trailing operator.
+///
+/// It is if every error found in it is caused by the end of the source.
+pub fn is_incomplete(source: &str) -> bool {
+    match gen_input(source) {
+        Err(Error::Lex(errors)) => errors.iter().all(|error| {
+            matches!(
+                error.kind,
+                LexErrorKind::UnterminatedString | LexErrorKind::UnterminatedComment
+            )
+        }),
+        Err(Error::Parse(errors)) => errors.iter().all(|error| error.found == TokenKind::Eof),
+        _ => false,
+    }
 }
 
-pub fn type_check(source: &str) -> Type {
-    type_checker::type_check(gen_ast(source), vec![])
+pub fn type_check(source: &str) -> Result<Type, Error> {
+    type_checker::type_check(gen_ast(source)?, vec![]).map_err(Error::Type)
 }
 
-pub fn run(source: &str) -> Value {
-    let ast = gen_ast(source);
+pub fn run(source: &str) -> Result<Value, Error> {
+    let ast = gen_ast(source)?;
     let ast_untyped = ast.type_erase();
-    type_checker::type_check(ast, vec![]);
-    interp::interp(ast_untyped, vec![])
+    type_checker::type_check(ast, vec![]).map_err(Error::Type)?;
+    interp::interp(ast_untyped, vec![]).map_err(Error::Runtime)
+}
+
+/// The state of a REPL, which keeps the top-level declarations of earlier inputs.
+///
+/// Declaring a name again replaces it for the following inputs,
+/// while functions defined before keep referring to the old binding.
+#[derive(Default)]
+pub struct Session {
+    tenv: TEnv,
+    env: Env,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// Type checks `source` against the declarations so far and returns the type of its last input.
+    ///
+    /// The type of a declaration is the type of its definition.
+    /// Declarations are only bound for the rest of `source`, and nothing is recorded in the session:
+    /// not even the types that using an earlier, ungeneralized declaration infers for it.
+    pub fn type_check(&self, source: &str) -> Result<Type, Error> {
+        let mut tenv = type_checker::fresh_copy(&self.tenv);
+        let mut typ = None;
+        for input in gen_input(source)? {
+            typ = Some(match input {
+                Input::Decl {
+                    rec,
+                    name,
+                    typ,
+                    expr,
+                } => {
+                    let scheme = type_checker::type_check_binding(&name, typ, expr, rec, &tenv)
+                        .map_err(Error::Type)?;
+                    let typ = scheme.typ.clone();
+                    declare(&mut tenv, name, scheme);
+                    typ
+                }
+                Input::Expr(expr) => {
+                    type_checker::type_check(expr, tenv.clone()).map_err(Error::Type)?
+                }
+            });
+        }
+        Ok(typ.expect("expected at least one input"))
+    }
+
+    /// Runs the inputs in `source` in order, recording their declarations, and returns the value of the last one.
+    ///
+    /// The value of a declaration is the value bound to its name.
+    /// A declaration is not recorded if it fails to type check or to run, and the inputs after it are not run.
+    pub fn run(&mut self, source: &str) -> Result<Value, Error> {
+        let mut value = None;
+        for input in gen_input(source)? {
+            value = Some(self.run_input(input)?);
+        }
+        Ok(value.expect("expected at least one input"))
+    }
+
+    fn run_input(&mut self, input: Input) -> Result<Value, Error> {
+        // The input is checked against a copy, which is only kept once it has run,
+        // since checking it can infer the types of earlier declarations that were not generalized.
+        let mut tenv = type_checker::fresh_copy(&self.tenv);
+        let value = match input {
+            Input::Decl {
+                rec,
+                name,
+                typ,
+                expr,
+            } => {
+                let ast_untyped = expr.type_erase();
+                let scheme = type_checker::type_check_binding(&name, typ, expr, rec, &tenv)
+                    .map_err(Error::Type)?;
+                let value = interp::interp_binding(&name, ast_untyped, rec, self.env.clone())
+                    .map_err(Error::Runtime)?;
+                declare(&mut tenv, name.clone(), scheme);
+                declare(&mut self.env, name, value.clone());
+                value
+            }
+            Input::Expr(expr) => {
+                let ast_untyped = expr.type_erase();
+                type_checker::type_check(expr, tenv.clone()).map_err(Error::Type)?;
+                interp::interp(ast_untyped, self.env.clone()).map_err(Error::Runtime)?
+            }
+        };
+        self.tenv = tenv;
+        Ok(value)
+    }
+
+    /// The names declared so far and their types, in the order they were first declared.
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, &Type)> {
+        self.tenv
+            .iter()
+            .map(|(name, scheme)| (name.as_str(), &scheme.typ))
+    }
+}
+
+// Binds `name` in `scope`, replacing an earlier binding of the same name.
+fn declare<T>(scope: &mut Vec<(String, T)>, name: String, value: T) {
+    match scope.iter_mut().find(|(x, _)| *x == name) {
+        Some((_, old)) => *old = value,
+        None => scope.push((name, value)),
+    }
 }
@@ -0,0 +1,90 @@
+use super::interp::RuntimeError;
+use super::span::Span;
+use super::typed::Type;
+use super::untyped::Value;
+
+/// A function provided by the language rather than defined in a script.
+///
+/// Builtins are looked up after every binding in scope, so a script can shadow them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    /// `length(s)`: the number of characters in `s`.
+    Length,
+    /// `at(s, i)`: the character at index `i` of `s`, as a string.
+    At,
+    /// `float(n)`: the number `n` as a float.
+    Float,
+    /// `truncate(x)`: the float `x` without its fractional part, as a number.
+    Truncate,
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin::Length,
+    Builtin::At,
+    Builtin::Float,
+    Builtin::Truncate,
+];
+
+impl Builtin {
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        BUILTINS
+            .iter()
+            .find(|builtin| builtin.name() == name)
+            .copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Length => "length",
+            Builtin::At => "at",
+            Builtin::Float => "float",
+            Builtin::Truncate => "truncate",
+        }
+    }
+
+    pub fn typ(self) -> Type {
+        match self {
+            Builtin::Length => Type::Fun(vec![Type::Str], Box::new(Type::Num)),
+            Builtin::At => Type::Fun(vec![Type::Str, Type::Num], Box::new(Type::Str)),
+            Builtin::Float => Type::Fun(vec![Type::Num], Box::new(Type::Float)),
+            Builtin::Truncate => Type::Fun(vec![Type::Float], Box::new(Type::Num)),
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::Length | Builtin::Float | Builtin::Truncate => 1,
+            Builtin::At => 2,
+        }
+    }
+
+    /// Applies the builtin to `args`, which has `self.arity()` values, in the application at `span`.
+    pub fn apply(self, args: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+        match (self, args.as_slice()) {
+            (Builtin::Length, [Value::Str(s)]) => Ok(Value::Num(s.chars().count() as isize)),
+            (Builtin::At, [Value::Str(s), Value::Num(index)]) => usize::try_from(*index)
+                .ok()
+                .and_then(|i| s.chars().nth(i))
+                .map(|c| Value::Str(c.to_string()))
+                .ok_or(RuntimeError::IndexOutOfBounds {
+                    index: *index,
+                    length: s.chars().count(),
+                    span,
+                }),
+            (Builtin::Float, [Value::Num(n)]) => Ok(Value::Float(*n as f64)),
+            // Infinities, NaN and floats beyond the range of `Num` have no truncation.
+            (Builtin::Truncate, [Value::Float(x)]) => {
+                let x = x.trunc();
+                if x >= isize::MIN as f64 && x < isize::MAX as f64 {
+                    Ok(Value::Num(x as isize))
+                } else {
+                    Err(RuntimeError::Overflow { span })
+                }
+            }
+            _ => Err(RuntimeError::BadArguments {
+                name: self.name().to_string(),
+                span,
+            }),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use core::fmt;
+
+use super::span::Span;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// A message attached to a region of the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(span: Span, message: impl Into<String>) -> Label {
+        Label {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+/// An error as shown to the user: a message, the span that caused it,
+/// and other spans that explain it, e.g. the operator that requires a type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The kind of error, e.g. `"Type error"`.
+    pub title: String,
+    pub message: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+}
+
+impl Diagnostic {
+    pub fn new(title: impl Into<String>, message: impl Into<String>, primary: Label) -> Diagnostic {
+        Diagnostic {
+            title: title.into(),
+            message: message.into(),
+            primary,
+            secondary: vec![],
+        }
+    }
+
+    /// Renders the diagnostic with the lines of `source` it refers to, underlining its labels.
+    ///
+    /// The primary label is underlined with `^` and the secondary ones with `-`.
+    /// With `color`, the output is highlighted with ANSI escape codes.
+    pub fn render(&self, source: &str, color: bool) -> String {
+        let paint = |code: &str, text: &str| {
+            if color {
+                format!("\x1b[{}m{}\x1b[0m", code, text)
+            } else {
+                text.to_string()
+            }
+        };
+
+        let labels: Vec<(&Label, bool)> = std::iter::once((&self.primary, true))
+            .chain(self.secondary.iter().map(|label| (label, false)))
+            .collect();
+        let mut lines: Vec<usize> = labels.iter().map(|(label, _)| label.span.line).collect();
+        lines.sort();
+        lines.dedup();
+        let width = lines.last().map_or(1, |line| line.to_string().len());
+        let gutter = |line: &str| paint(BLUE, &format!("{:>width$} |", line, width = width));
+
+        let mut out = format!(
+            "{}: {}\n{}{} line {}:{}\n{}",
+            paint(RED, &self.title),
+            self.message,
+            " ".repeat(width),
+            paint(BLUE, "-->"),
+            self.primary.span.line,
+            self.primary.span.col,
+            gutter("")
+        );
+        for line in lines {
+            let span = labels
+                .iter()
+                .map(|(label, _)| label.span)
+                .find(|span| span.line == line)
+                .unwrap();
+            let (start, text) = source_line(source, span);
+            out.push('\n');
+            out.push_str(&format!("{} {}", gutter(&line.to_string()), text));
+
+            for (label, primary) in labels.iter().filter(|(label, _)| label.span.line == line) {
+                let from = label.span.start.clamp(start, start + text.len());
+                let to = label.span.end.clamp(from, start + text.len());
+                // Tabs are kept and wide characters are padded with as many spaces as
+                // the columns they take, so that the underline lines up with the text above it.
+                let padding: String = text[..from - start]
+                    .chars()
+                    .map(|c| match c {
+                        '\t' => "\t".to_string(),
+                        c => " ".repeat(c.width().unwrap_or(0)),
+                    })
+                    .collect();
+                let (mark, code) = if *primary { ('^', RED) } else { ('-', BLUE) };
+                let underline = mark
+                    .to_string()
+                    .repeat(text[from - start..to - start].width().max(1));
+                out.push('\n');
+                out.push_str(&format!(
+                    "{} {}{}",
+                    gutter(""),
+                    padding,
+                    paint(code, &format!("{} {}", underline, label.message))
+                ));
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.primary.span;
+        write!(
+            f,
+            "[line {}:{}] {}: {}",
+            span.line, span.col, self.title, self.message
+        )
+    }
+}
+
+const RED: &str = "1;31";
+const BLUE: &str = "1;34";
+
+// Returns the byte offset and the text, without its line break, of the line where `span` starts.
+fn source_line(source: &str, span: Span) -> (usize, &str) {
+    let at = span.start.min(source.len());
+    let start = source[..at].rfind('\n').map_or(0, |i| i + 1);
+    let end = source[at..].find('\n').map_or(source.len(), |i| at + i);
+    (start, source[start..end].trim_end_matches('\r'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let source = "1 +\n  true";
+        let mut diagnostic = Diagnostic::new(
+            "Type error",
+            "Expected Num, found Bool.",
+            Label::new(Span::new(6, 10, 2, 3), "found Bool"),
+        );
+        diagnostic.secondary.push(Label::new(
+            Span::new(0, 10, 1, 1),
+            "expected Num because of this `+` expression",
+        ));
+        assert_eq!(
+            diagnostic.render(source, false),
+            "\
+Type error: Expected Num, found Bool.
+ --> line 2:3
+  |
+1 | 1 +
+  | --- expected Num because of this `+` expression
+2 |   true
+  |   ^^^^ found Bool"
+        );
+        assert_eq!(
+            diagnostic.to_string(),
+            "[line 2:3] Type error: Expected Num, found Bool."
+        );
+    }
+
+    #[test]
+    fn render_same_line() {
+        let source = "\tx == true";
+        let mut diagnostic = Diagnostic::new(
+            "Type error",
+            "Expected Num, found Bool.",
+            Label::new(Span::new(6, 10, 1, 7), "found Bool"),
+        );
+        diagnostic.secondary.push(Label::new(
+            Span::new(1, 2, 1, 2),
+            "expected because of this",
+        ));
+        assert_eq!(
+            diagnostic.render(source, false),
+            "\
+Type error: Expected Num, found Bool.
+ --> line 1:7
+  |
+1 | \tx == true
+  | \t     ^^^^ found Bool
+  | \t- expected because of this"
+        );
+    }
+
+    #[test]
+    fn render_wide_characters() {
+        let source = "名前 + true";
+        let mut diagnostic = Diagnostic::new(
+            "Type error",
+            "Expected Num, found Bool.",
+            Label::new(Span::new(9, 13, 1, 6), "found Bool"),
+        );
+        diagnostic
+            .secondary
+            .push(Label::new(Span::new(0, 6, 1, 1), "this"));
+        assert_eq!(
+            diagnostic.render(source, false),
+            "\
+Type error: Expected Num, found Bool.
+ --> line 1:6
+  |
+1 | 名前 + true
+  |        ^^^^ found Bool
+  | ---- this"
+        );
+    }
+
+    #[test]
+    fn render_end_of_input() {
+        let diagnostic = Diagnostic::new(
+            "Error at end",
+            "Expected expression.",
+            Label::new(Span::new(3, 3, 1, 4), "expected expression"),
+        );
+        assert_eq!(
+            diagnostic.render("1 +", true),
+            "\x1b[1;31mError at end\x1b[0m: Expected expression.
+ \x1b[1;34m-->\x1b[0m line 1:4
+\x1b[1;34m  |\x1b[0m
+\x1b[1;34m1 |\x1b[0m 1 +
+\x1b[1;34m  |\x1b[0m    \x1b[1;31m^ expected expression\x1b[0m"
+        );
+    }
+}
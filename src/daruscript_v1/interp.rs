@@ -1,61 +1,494 @@
 use core::fmt;
+use std::cell::Cell;
 
+use super::builtin::Builtin;
+use super::diagnostic::{Diagnostic, Label};
+use super::span::Span;
+use super::stack;
 use super::untyped::*;
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    /// The right-hand side of a division or remainder evaluated to zero.
+    DivisionByZero { span: Span },
+    /// The exponent of a `**` evaluated to a negative number, whose power is not a `Num`.
+    NegativeExponent { span: Span },
+    /// An arithmetic operation whose result does not fit in a `Num`.
+    Overflow { span: Span },
+    /// An identifier that is not bound.
+    UnboundIdent { name: String, span: Span },
+    /// An application of a value that is not a function.
+    NotAFunction { span: Span },
+    /// An application with a different number of arguments than the function has parameters.
+    Arity {
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// An arithmetic or comparison operand that is not a number.
+    NotANumber { span: Span },
+    /// A logical operand that is not a boolean.
+    NotABool { span: Span },
+    /// An equality between values that cannot be compared, such as functions.
+    NotComparable { span: Span },
+    /// A concatenation operand that is not a string.
+    NotAString { span: Span },
+    /// An index past the end of a string, or a negative one.
+    IndexOutOfBounds {
+        index: isize,
+        length: usize,
+        span: Span,
+    },
+    /// A builtin applied to arguments of the wrong types.
+    BadArguments { name: String, span: Span },
+    /// An evaluation nested deeper than `MAX_DEPTH`, usually by a recursion that does not end.
+    StackOverflow { span: Span },
+}
+
+impl RuntimeError {
+    pub fn span(&self) -> Span {
+        match self {
+            RuntimeError::DivisionByZero { span }
+            | RuntimeError::NegativeExponent { span }
+            | RuntimeError::Overflow { span }
+            | RuntimeError::UnboundIdent { span, .. }
+            | RuntimeError::NotAFunction { span }
+            | RuntimeError::Arity { span, .. }
+            | RuntimeError::NotANumber { span }
+            | RuntimeError::NotABool { span }
+            | RuntimeError::NotComparable { span }
+            | RuntimeError::NotAString { span }
+            | RuntimeError::IndexOutOfBounds { span, .. }
+            | RuntimeError::BadArguments { span, .. }
+            | RuntimeError::StackOverflow { span } => *span,
+        }
+    }
+}
+
+impl RuntimeError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let (message, label) = match self {
+            RuntimeError::DivisionByZero { .. } => (
+                "Division by zero.".to_string(),
+                "divisor is zero".to_string(),
+            ),
+            RuntimeError::NegativeExponent { .. } => (
+                "Negative exponent.".to_string(),
+                "exponent is negative".to_string(),
+            ),
+            RuntimeError::Overflow { .. } => (
+                "Integer overflow.".to_string(),
+                "result does not fit in a number".to_string(),
+            ),
+            RuntimeError::UnboundIdent { name, .. } => (
+                format!("Unbound identifier '{}'.", name),
+                "not bound".to_string(),
+            ),
+            RuntimeError::NotAFunction { .. } => (
+                "Only functions can be applied.".to_string(),
+                "not a function".to_string(),
+            ),
+            RuntimeError::Arity {
+                expected, found, ..
+            } => (
+                format!("Expected {} arguments, found {}.", expected, found),
+                format!("applied to {} arguments", found),
+            ),
+            RuntimeError::NotANumber { .. } => (
+                "Operand must be a number.".to_string(),
+                "not a number".to_string(),
+            ),
+            RuntimeError::NotABool { .. } => (
+                "Operand must be a boolean.".to_string(),
+                "not a boolean".to_string(),
+            ),
+            RuntimeError::NotComparable { .. } => (
+                "Only numbers, floats, booleans and strings can be compared for equality."
+                    .to_string(),
+                "cannot be compared".to_string(),
+            ),
+            RuntimeError::NotAString { .. } => (
+                "Operand must be a string.".to_string(),
+                "not a string".to_string(),
+            ),
+            RuntimeError::IndexOutOfBounds { index, length, .. } => (
+                format!("Index {} out of bounds for length {}.", index, length),
+                "index out of bounds".to_string(),
+            ),
+            RuntimeError::BadArguments { name, .. } => (
+                format!("Invalid arguments to '{}'.", name),
+                "invalid arguments".to_string(),
+            ),
+            RuntimeError::StackOverflow { .. } => (
+                format!(
+                    "Stack overflow: evaluation nested deeper than {}.",
+                    MAX_DEPTH
+                ),
+                "nested too deeply".to_string(),
+            ),
+        };
+        Diagnostic::new("Runtime error", message, Label::new(self.span(), label))
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.diagnostic())
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::Num(n) => write!(f, "{}", n),
-            Value::Closure(p, b, e) => write!(f, "Closure({}, {}, {:?})", p, b.to_string(), e),
+            // Debug formatting keeps the decimal point of whole numbers, e.g. `2.0`.
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Str(s) => write!(f, "{:?}", s),
+            // The captured environment is left out, as it can hold every binding in scope.
+            Value::Closure(p, b, _) => write!(f, "Closure({}, {})", p.join(", "), b),
+            Value::RecClosure(n, p, b, _) => {
+                write!(f, "RecClosure({}, {}, {})", n, p.join(", "), b)
+            }
+            Value::Builtin(builtin) => write!(f, "Builtin({})", builtin.name()),
         }
     }
 }
 
-pub fn interp(expr: Expr, mut env: Env) -> Value {
-    match expr {
-        Expr::Num(n) => Value::Num(n),
-        Expr::Add(l, r) => {
-            if let (Value::Num(l), Value::Num(r)) = (interp(*l, env.clone()), interp(*r, env)) {
-                Value::Num(l + r)
-            } else {
-                panic!("incompatible types");
-            }
-        }
-        Expr::Sub(l, r) => {
-            if let (Value::Num(l), Value::Num(r)) = (interp(*l, env.clone()), interp(*r, env)) {
-                Value::Num(l - r)
-            } else {
-                panic!("incompatible types");
+/// The deepest nesting of `interp` calls, so that a runaway recursion is an error
+/// instead of growing the stack until memory runs out.
+const MAX_DEPTH: usize = 50_000;
+
+thread_local! {
+    // The number of `interp` calls being evaluated on this thread.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+// Counts an `interp` call for as long as it lives.
+struct DepthGuard;
+
+impl DepthGuard {
+    fn enter(span: Span) -> Result<DepthGuard, RuntimeError> {
+        DEPTH.with(|depth| {
+            if depth.get() >= MAX_DEPTH {
+                return Err(RuntimeError::StackOverflow { span });
             }
+            depth.set(depth.get() + 1);
+            Ok(DepthGuard)
+        })
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+pub fn interp(expr: Expr, env: Env) -> Result<Value, RuntimeError> {
+    let _depth = DepthGuard::enter(expr.span)?;
+    stack::grow(|| eval(expr, env))
+}
+
+fn eval(expr: Expr, env: Env) -> Result<Value, RuntimeError> {
+    // The operations are evaluated by separate functions, which keeps this frame small
+    // since it is on the stack once for every level of nesting.
+    let span = expr.span;
+    match expr.into_kind() {
+        ExprKind::Num(n) => Ok(Value::Num(n)),
+        ExprKind::Float(x) => Ok(Value::Float(x)),
+        ExprKind::Bool(b) => Ok(Value::Bool(b)),
+        ExprKind::Str(s) => Ok(Value::Str(s)),
+        ExprKind::Add(l, r) => arith(*l, *r, env, span, isize::checked_add),
+        ExprKind::Sub(l, r) => arith(*l, *r, env, span, isize::checked_sub),
+        ExprKind::Mul(l, r) => arith(*l, *r, env, span, isize::checked_mul),
+        ExprKind::Div(l, r) => divide(*l, *r, env, span, isize::checked_div),
+        ExprKind::Mod(l, r) => divide(*l, *r, env, span, isize::checked_rem),
+        ExprKind::Pow(l, r) => pow(*l, *r, env, span),
+        // IEEE 754 arithmetic, where dividing by zero gives an infinity or NaN.
+        ExprKind::FAdd(l, r) => float_op(*l, *r, env, |l, r| Value::Float(l + r)),
+        ExprKind::FSub(l, r) => float_op(*l, *r, env, |l, r| Value::Float(l - r)),
+        ExprKind::FMul(l, r) => float_op(*l, *r, env, |l, r| Value::Float(l * r)),
+        ExprKind::FDiv(l, r) => float_op(*l, *r, env, |l, r| Value::Float(l / r)),
+        ExprKind::FNeg(expr) => interp_float(*expr, env).map(|x| Value::Float(-x)),
+        ExprKind::FLt(l, r) => float_op(*l, *r, env, |l, r| Value::Bool(l < r)),
+        ExprKind::FLe(l, r) => float_op(*l, *r, env, |l, r| Value::Bool(l <= r)),
+        ExprKind::FGt(l, r) => float_op(*l, *r, env, |l, r| Value::Bool(l > r)),
+        ExprKind::FGe(l, r) => float_op(*l, *r, env, |l, r| Value::Bool(l >= r)),
+        ExprKind::Concat(l, r) => concat(*l, *r, env),
+        ExprKind::Eq(l, r) => interp_eq(*l, *r, env, span).map(Value::Bool),
+        ExprKind::Ne(l, r) => interp_eq(*l, *r, env, span).map(|eq| Value::Bool(!eq)),
+        ExprKind::Lt(l, r) => compare(*l, *r, env, |l, r| l < r),
+        ExprKind::Le(l, r) => compare(*l, *r, env, |l, r| l <= r),
+        ExprKind::Gt(l, r) => compare(*l, *r, env, |l, r| l > r),
+        ExprKind::Ge(l, r) => compare(*l, *r, env, |l, r| l >= r),
+        ExprKind::Not(expr) => interp_bool(*expr, env).map(|b| Value::Bool(!b)),
+        ExprKind::And(l, r) => short_circuit(*l, *r, env, false),
+        ExprKind::Or(l, r) => short_circuit(*l, *r, env, true),
+        ExprKind::If(cond, then, otherwise) => branch(*cond, *then, *otherwise, env),
+        ExprKind::Val(name, expr, body) => bind(name, *expr, *body, false, env),
+        ExprKind::Rec(name, expr, body) => bind(name, *expr, *body, true, env),
+        ExprKind::Id(ident) => lookup(ident, &env, span),
+        ExprKind::Fun(params, body) => Ok(Value::Closure(params, *body, env)),
+        ExprKind::App(func, args) => apply(*func, args, env, span),
+    }
+}
+
+// Evaluates the integer operation `op` at `span`, which overflows if it returns `None`.
+fn arith(
+    l: Expr,
+    r: Expr,
+    env: Env,
+    span: Span,
+    op: fn(isize, isize) -> Option<isize>,
+) -> Result<Value, RuntimeError> {
+    let (l, r) = (interp_num(l, env.clone())?, interp_num(r, env)?);
+    op(l, r)
+        .map(Value::Num)
+        .ok_or(RuntimeError::Overflow { span })
+}
+
+// Evaluates a division or remainder `op` at `span`, whose right operand must not be zero.
+fn divide(
+    l: Expr,
+    r: Expr,
+    env: Env,
+    span: Span,
+    op: fn(isize, isize) -> Option<isize>,
+) -> Result<Value, RuntimeError> {
+    let right_span = r.span;
+    let (l, r) = (interp_num(l, env.clone())?, interp_num(r, env)?);
+    if r == 0 {
+        return Err(RuntimeError::DivisionByZero { span: right_span });
+    }
+    op(l, r)
+        .map(Value::Num)
+        .ok_or(RuntimeError::Overflow { span })
+}
+
+fn pow(l: Expr, r: Expr, env: Env, span: Span) -> Result<Value, RuntimeError> {
+    let right_span = r.span;
+    let (l, r) = (interp_num(l, env.clone())?, interp_num(r, env)?);
+    if r < 0 {
+        return Err(RuntimeError::NegativeExponent { span: right_span });
+    }
+    // The only bases whose powers fit in a `Num` for exponents past `u32::MAX`.
+    match l {
+        0 | 1 => return Ok(Value::Num(if r == 0 { 1 } else { l })),
+        -1 => return Ok(Value::Num(if r % 2 == 0 { 1 } else { -1 })),
+        _ => (),
+    }
+    u32::try_from(r)
+        .ok()
+        .and_then(|r| l.checked_pow(r))
+        .map(Value::Num)
+        .ok_or(RuntimeError::Overflow { span })
+}
+
+fn float_op(l: Expr, r: Expr, env: Env, op: fn(f64, f64) -> Value) -> Result<Value, RuntimeError> {
+    let (l, r) = (interp_float(l, env.clone())?, interp_float(r, env)?);
+    Ok(op(l, r))
+}
+
+fn compare(
+    l: Expr,
+    r: Expr,
+    env: Env,
+    op: fn(isize, isize) -> bool,
+) -> Result<Value, RuntimeError> {
+    let (l, r) = (interp_num(l, env.clone())?, interp_num(r, env)?);
+    Ok(Value::Bool(op(l, r)))
+}
+
+fn concat(l: Expr, r: Expr, env: Env) -> Result<Value, RuntimeError> {
+    let l = interp_str(l, env.clone())?;
+    Ok(Value::Str(l + &interp_str(r, env)?))
+}
+
+// Evaluates `and` or `or`, where the right operand is only evaluated if the left one is not `decided`,
+// the value that decides the result.
+fn short_circuit(l: Expr, r: Expr, env: Env, decided: bool) -> Result<Value, RuntimeError> {
+    if interp_bool(l, env.clone())? == decided {
+        Ok(Value::Bool(decided))
+    } else {
+        interp_bool(r, env).map(Value::Bool)
+    }
+}
+
+fn branch(cond: Expr, then: Expr, otherwise: Expr, env: Env) -> Result<Value, RuntimeError> {
+    if interp_bool(cond, env.clone())? {
+        interp(then, env)
+    } else {
+        interp(otherwise, env)
+    }
+}
+
+// Evaluates `body` with `name` bound to the value of `expr`, which can refer to itself if `rec`.
+fn bind(
+    name: String,
+    expr: Expr,
+    body: Expr,
+    rec: bool,
+    mut env: Env,
+) -> Result<Value, RuntimeError> {
+    let value = interp_binding(&name, expr, rec, env.clone())?;
+    env.push((name, value));
+    interp(body, env)
+}
+
+// The innermost binding shadows the others, and every binding shadows the builtins.
+fn lookup(ident: String, env: &Env, span: Span) -> Result<Value, RuntimeError> {
+    match env.iter().rev().find(|(x, _)| *x == ident) {
+        Some((_, value)) => Ok(value.clone()),
+        None => match Builtin::lookup(&ident) {
+            Some(builtin) => Ok(Value::Builtin(builtin)),
+            None => Err(RuntimeError::UnboundIdent { name: ident, span }),
+        },
+    }
+}
+
+// Evaluates the application at `span` of `func` to `args`.
+fn apply(func: Expr, args: Vec<Expr>, env: Env, span: Span) -> Result<Value, RuntimeError> {
+    let func_span = func.span;
+    let func = interp(func, env.clone())?;
+    let (params, body, mut fenv) = match func {
+        Value::Closure(params, body, fenv) => (params, body, fenv),
+        Value::RecClosure(name, params, body, mut fenv) => {
+            let this = Value::RecClosure(name.clone(), params.clone(), body.clone(), fenv.clone());
+            fenv.push((name, this));
+            (params, body, fenv)
         }
-        Expr::Mul(l, r) => {
-            if let (Value::Num(l), Value::Num(r)) = (interp(*l, env.clone()), interp(*r, env)) {
-                Value::Num(l * r)
-            } else {
-                panic!("incompatible types");
-            }
+        Value::Builtin(builtin) => {
+            check_arity(builtin.arity(), args.len(), span)?;
+            let args = args
+                .into_iter()
+                .map(|arg| interp(arg, env.clone()))
+                .collect::<Result<_, _>>()?;
+            return builtin.apply(args, span);
         }
-        Expr::Div(l, r) => {
-            if let (Value::Num(l), Value::Num(r)) = (interp(*l, env.clone()), interp(*r, env)) {
-                Value::Num(l / r)
-            } else {
-                panic!("incompatible types");
-            }
+        _ => return Err(RuntimeError::NotAFunction { span: func_span }),
+    };
+    check_arity(params.len(), args.len(), span)?;
+    for (param, arg) in params.into_iter().zip(args) {
+        fenv.push((param, interp(arg, env.clone())?));
+    }
+    interp(body, fenv)
+}
+
+/// Evaluates the definition `expr` of `name`, which can refer to itself if `rec`.
+pub fn interp_binding(name: &str, expr: Expr, rec: bool, env: Env) -> Result<Value, RuntimeError> {
+    Ok(match interp(expr, env)? {
+        Value::Closure(params, body, fenv) if rec => {
+            Value::RecClosure(name.to_string(), params, body, fenv)
         }
-        Expr::Val(name, expr, body) => interp(*body, {
-            env.push((name, interp(*expr, env.clone())));
-            env
-        }),
-        Expr::Id(ident) => env
-            .iter()
-            .find(|(x, _)| *x == ident)
-            .expect("free identifier error")
-            .1
-            .clone(),
-        Expr::Fun(param, body) => Value::Closure(param, *body, env),
-        Expr::App(func, arg) => match interp(*func, env.clone()) {
-            Value::Closure(x, b, mut fenv) => interp(b, { fenv.push((x, interp(*arg, env))); fenv }),
-            _ => panic!("incompatible type"),
-        },
+        value => value,
+    })
+}
+
+// Checks that the application at `span` has as many arguments as the function has parameters.
+fn check_arity(expected: usize, found: usize, span: Span) -> Result<(), RuntimeError> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(RuntimeError::Arity {
+            expected,
+            found,
+            span,
+        })
+    }
+}
+
+// Evaluates a logical operand.
+fn interp_bool(expr: Expr, env: Env) -> Result<bool, RuntimeError> {
+    let span = expr.span;
+    match interp(expr, env)? {
+        Value::Bool(b) => Ok(b),
+        _ => Err(RuntimeError::NotABool { span }),
+    }
+}
+
+// Evaluates the operands of an equality at `span` and compares them.
+fn interp_eq(l: Expr, r: Expr, env: Env, span: Span) -> Result<bool, RuntimeError> {
+    match (interp(l, env.clone())?, interp(r, env)?) {
+        (Value::Num(l), Value::Num(r)) => Ok(l == r),
+        (Value::Float(l), Value::Float(r)) => Ok(l == r),
+        (Value::Bool(l), Value::Bool(r)) => Ok(l == r),
+        (Value::Str(l), Value::Str(r)) => Ok(l == r),
+        _ => Err(RuntimeError::NotComparable { span }),
+    }
+}
+
+// Evaluates a floating-point operand.
+fn interp_float(expr: Expr, env: Env) -> Result<f64, RuntimeError> {
+    let span = expr.span;
+    match interp(expr, env)? {
+        Value::Float(x) => Ok(x),
+        _ => Err(RuntimeError::NotANumber { span }),
+    }
+}
+
+// Evaluates a concatenation operand.
+fn interp_str(expr: Expr, env: Env) -> Result<String, RuntimeError> {
+    let span = expr.span;
+    match interp(expr, env)? {
+        Value::Str(s) => Ok(s),
+        _ => Err(RuntimeError::NotAString { span }),
+    }
+}
+
+// Evaluates an arithmetic or comparison operand.
+fn interp_num(expr: Expr, env: Env) -> Result<isize, RuntimeError> {
+    let span = expr.span;
+    match interp(expr, env)? {
+        Value::Num(n) => Ok(n),
+        _ => Err(RuntimeError::NotANumber { span }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{interp, RuntimeError};
+    use crate::daruscript_v1::gen_ast;
+    use crate::daruscript_v1::span::Span;
+
+    // Evaluates without type checking, to reach errors the type checker would catch first.
+    fn interp_unchecked(source: &str) -> Result<String, RuntimeError> {
+        let ast = gen_ast(source).unwrap().type_erase();
+        interp(ast, vec![]).map(|value| value.to_string())
+    }
+
+    #[test]
+    fn untyped_errors() {
+        assert_eq!(
+            interp_unchecked("1 + y"),
+            Err(RuntimeError::UnboundIdent {
+                name: "y".to_string(),
+                span: Span::new(4, 5, 1, 5)
+            })
+        );
+        assert_eq!(
+            interp_unchecked("{x => x(x)}(3)"),
+            Err(RuntimeError::NotAFunction {
+                span: Span::new(6, 7, 1, 7)
+            })
+        );
+        assert_eq!(
+            interp_unchecked("2 * {x => x}"),
+            Err(RuntimeError::NotANumber {
+                span: Span::new(4, 12, 1, 5)
+            })
+        );
+        assert_eq!(
+            interp_unchecked("{x => 1 / x}(0) + y").map_err(|e| e.span().start),
+            Err(10)
+        );
+        assert_eq!(
+            interp_unchecked("{x, y => x}(1)"),
+            Err(RuntimeError::Arity {
+                expected: 2,
+                found: 1,
+                span: Span::new(0, 14, 1, 1)
+            })
+        );
     }
 }
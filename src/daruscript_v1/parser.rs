@@ -1,19 +1,262 @@
+use super::diagnostic::{Diagnostic, Label};
 use super::scanner::{Literal, Token, TokenKind};
+use super::span::Span;
+use super::stack;
 use super::typed::Type;
-use super::typed::Expr;
+use super::typed::{Expr, ExprKind, Input};
+use core::fmt;
 use std::cell::RefCell;
 use std::iter::Peekable;
 use std::rc::Rc;
 use std::vec::IntoIter;
 
+/// A syntax error: the parser expected one thing and found a different token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// A description of what was expected, e.g. `"')' after expression"`.
+    pub expected: String,
+    /// The kind of the token found instead.
+    pub found: TokenKind,
+    /// The lexeme of the token found instead.
+    pub lexeme: String,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let title = if self.found == TokenKind::Eof {
+            "Error at end".to_string()
+        } else {
+            format!("Error at '{}'", self.lexeme)
+        };
+        Diagnostic::new(
+            title,
+            format!("Expected {}.", self.expected),
+            Label::new(self.span, format!("expected {}", self.expected)),
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.diagnostic())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assoc {
+    Left,
+    Right,
+}
+
+/// An infix operator: the token it is written with, how tightly it binds and the expression it builds.
+struct Infix {
+    token: TokenKind,
+    precedence: u8,
+    assoc: Assoc,
+    build: fn(Box<Expr>, Box<Expr>) -> ExprKind,
+}
+
+/// The infix operators, from the loosest to the tightest binding.
+///
+/// | Precedence | Operators                               | Associativity |
+/// |------------|-----------------------------------------|---------------|
+/// | 1          | `\|>`                                   | left          |
+/// | 2          | `or`                                    | left          |
+/// | 3          | `and`                                   | left          |
+/// | 4          | `==` `!=`                               | left          |
+/// | 5          | `<` `<=` `>` `>=` `<.` `<=.` `>.` `>=.` | left          |
+/// | 6          | `+` `-` `++` `+.` `-.`                  | left          |
+/// | 7          | `*` `/` `%` `*.` `/.`                   | left          |
+/// | 8          | prefix `!` `-` `-.`                     |               |
+/// | 9          | `**`                                    | right         |
+///
+/// Application `f(x)` binds tighter than any operator.
+const INFIX: &[Infix] = &[
+    Infix {
+        token: TokenKind::Pipe,
+        precedence: 1,
+        assoc: Assoc::Left,
+        // `x |> f` is `f(x)`.
+        build: |arg, func| ExprKind::App(func, vec![*arg]),
+    },
+    Infix {
+        token: TokenKind::Or,
+        precedence: 2,
+        assoc: Assoc::Left,
+        build: ExprKind::Or,
+    },
+    Infix {
+        token: TokenKind::And,
+        precedence: 3,
+        assoc: Assoc::Left,
+        build: ExprKind::And,
+    },
+    Infix {
+        token: TokenKind::EqualsEquals,
+        precedence: 4,
+        assoc: Assoc::Left,
+        build: ExprKind::Eq,
+    },
+    Infix {
+        token: TokenKind::BangEquals,
+        precedence: 4,
+        assoc: Assoc::Left,
+        build: ExprKind::Ne,
+    },
+    Infix {
+        token: TokenKind::Less,
+        precedence: 5,
+        assoc: Assoc::Left,
+        build: ExprKind::Lt,
+    },
+    Infix {
+        token: TokenKind::LessOrEquals,
+        precedence: 5,
+        assoc: Assoc::Left,
+        build: ExprKind::Le,
+    },
+    Infix {
+        token: TokenKind::Greater,
+        precedence: 5,
+        assoc: Assoc::Left,
+        build: ExprKind::Gt,
+    },
+    Infix {
+        token: TokenKind::GreaterOrEquals,
+        precedence: 5,
+        assoc: Assoc::Left,
+        build: ExprKind::Ge,
+    },
+    Infix {
+        token: TokenKind::LessDot,
+        precedence: 5,
+        assoc: Assoc::Left,
+        build: ExprKind::FLt,
+    },
+    Infix {
+        token: TokenKind::LessOrEqualsDot,
+        precedence: 5,
+        assoc: Assoc::Left,
+        build: ExprKind::FLe,
+    },
+    Infix {
+        token: TokenKind::GreaterDot,
+        precedence: 5,
+        assoc: Assoc::Left,
+        build: ExprKind::FGt,
+    },
+    Infix {
+        token: TokenKind::GreaterOrEqualsDot,
+        precedence: 5,
+        assoc: Assoc::Left,
+        build: ExprKind::FGe,
+    },
+    Infix {
+        token: TokenKind::Plus,
+        precedence: 6,
+        assoc: Assoc::Left,
+        build: ExprKind::Add,
+    },
+    Infix {
+        token: TokenKind::Minus,
+        precedence: 6,
+        assoc: Assoc::Left,
+        build: ExprKind::Sub,
+    },
+    Infix {
+        token: TokenKind::PlusPlus,
+        precedence: 6,
+        assoc: Assoc::Left,
+        build: ExprKind::Concat,
+    },
+    Infix {
+        token: TokenKind::PlusDot,
+        precedence: 6,
+        assoc: Assoc::Left,
+        build: ExprKind::FAdd,
+    },
+    Infix {
+        token: TokenKind::MinusDot,
+        precedence: 6,
+        assoc: Assoc::Left,
+        build: ExprKind::FSub,
+    },
+    Infix {
+        token: TokenKind::Star,
+        precedence: 7,
+        assoc: Assoc::Left,
+        build: ExprKind::Mul,
+    },
+    Infix {
+        token: TokenKind::Slash,
+        precedence: 7,
+        assoc: Assoc::Left,
+        build: ExprKind::Div,
+    },
+    Infix {
+        token: TokenKind::Percent,
+        precedence: 7,
+        assoc: Assoc::Left,
+        build: ExprKind::Mod,
+    },
+    Infix {
+        token: TokenKind::StarDot,
+        precedence: 7,
+        assoc: Assoc::Left,
+        build: ExprKind::FMul,
+    },
+    Infix {
+        token: TokenKind::SlashDot,
+        precedence: 7,
+        assoc: Assoc::Left,
+        build: ExprKind::FDiv,
+    },
+    Infix {
+        token: TokenKind::StarStar,
+        precedence: 9,
+        assoc: Assoc::Right,
+        build: ExprKind::Pow,
+    },
+];
+
+/// The precedence of the operands of prefix `!`, `-` and `-.`, so that `-x ** 2` is `-(x ** 2)`
+/// while `-x * 2` is `(-x) * 2`.
+const PREFIX_PRECEDENCE: u8 = 8;
+
+fn infix(token: &TokenKind) -> Option<&'static Infix> {
+    INFIX.iter().find(|op| op.token == *token)
+}
+
+/// Marks a failed parse whose error has already been recorded in `Parser::errors`.
+#[derive(Debug)]
+struct ErrorReported;
+
+type ParseResult<T> = Result<T, ErrorReported>;
+
 pub struct Parser {
     pub tokens: Peekable<IntoIter<Token>>,
+    errors: Vec<ParseError>,
+    // Set after an error and cleared once a `;` or `}` is consumed.
+    // Errors found in panic mode are most likely caused by the first one, so they are not recorded.
+    panic_mode: bool,
+    // How deeply the expression or type being parsed is nested; see `MAX_NESTING`.
+    depth: usize,
+    // Whether the next literal directly follows a prefix `-`, which is folded into it.
+    negated: bool,
 }
 
+/// The deepest nesting of expressions and types that is parsed.
+///
+/// Parentheses, blocks, functions, prefix operators and the right operands of right-associative operators nest,
+/// while chains of left-associative operators and of applications do not, however long they are.
+const MAX_NESTING: usize = 256;
+
 // Returns a new type variable and let the type be inferred.
 fn omit_type() -> Type {
     Type::Var {
         typ: Rc::new(RefCell::new(None)),
+        eq: false,
     }
 }
 
@@ -21,128 +264,143 @@ impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
         Parser {
             tokens: tokens.into_iter().peekable(),
+            errors: vec![],
+            panic_mode: false,
+            depth: 0,
+            negated: false,
         }
     }
 
-    pub fn parse(&mut self) -> Option<Expr> {
-        if self
-            .tokens
-            .peek()
-            .is_some_and(|tok| tok.kind == TokenKind::Eof)
-            || self.tokens.peek().is_none()
-        {
-            None
-        } else {
-            let expr = Some(self.expression());
-            if self.advance().expect("expected EOF").kind != TokenKind::Eof {
-                panic!("expected EOF")
-            }
-            expr
+    /// Parses the tokens as a single expression.
+    ///
+    /// Parsing recovers from syntax errors at `;` and `}`, so all the errors found are returned together.
+    pub fn parse(&mut self) -> Result<Expr, Vec<ParseError>> {
+        let expr = self.expression();
+        if !self.is_at_end() {
+            self.error("end of input");
         }
-    }
 
-    fn expression(&mut self) -> Expr {
-        self.parse_equality()
+        match expr {
+            Ok(expr) if self.errors.is_empty() => Ok(expr),
+            _ => Err(std::mem::take(&mut self.errors)),
+        }
     }
 
-    fn parse_equality(&mut self) -> Expr {
-        let expr = self.parse_comparison();
-
-        while let Some(kind) = [TokenKind::BangEquals, TokenKind::EqualsEquals]
-            .iter()
-            .find(|x| self.check(x))
-        {
-            self.advance().expect("expected a token");
-            let _right = self.parse_comparison();
-            expr = match kind {
-                TokenKind::Plus => todo!(),
-                TokenKind::Minus => todo!(),
-                _ => panic!("expected BangEquals or EqualsEquals"), // todo! error handling
-            };
+    /// Parses the tokens as REPL input: top-level declarations and expressions separated by `;`.
+    ///
+    /// A declaration is a `val` or `def` binding without a body. The last input can also end with a `;`.
+    /// Parsing recovers from a syntax error at the next `;`, like `parse`.
+    pub fn parse_input(&mut self) -> Result<Vec<Input>, Vec<ParseError>> {
+        let mut inputs = vec![];
+        loop {
+            let input = self.parse_top_level().and_then(|input| {
+                if !self.is_at_end() {
+                    self.consume(&TokenKind::Semicolon, "';' or end of input")?;
+                }
+                Ok(input)
+            });
+            match input {
+                Ok(input) => inputs.push(input),
+                Err(ErrorReported) => self.skip_input(),
+            }
+            if self.is_at_end() {
+                break;
+            }
         }
 
-        expr
+        if self.errors.is_empty() {
+            Ok(inputs)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
     }
 
-    fn parse_comparison(&mut self) -> Expr {
-        let expr = self.parse_term();
-
-        while let Some(kind) = [
-            TokenKind::Greater,
-            TokenKind::GreaterOrEquals,
-            TokenKind::Less,
-            TokenKind::LessOrEquals,
-        ]
-        .iter()
-        .find(|x| self.check(x))
-        {
-            self.advance().expect("expected a token");
-            let _right = self.parse_term();
-            expr = match kind {
-                TokenKind::Greater => todo!(),
-                TokenKind::GreaterOrEquals => todo!(),
-                TokenKind::Less => todo!(),
-                TokenKind::LessOrEquals => todo!(),
-                _ => panic!("expected Plus or Minus"), // todo! error handling
-            };
+    fn parse_top_level(&mut self) -> ParseResult<Input> {
+        if self.check(&TokenKind::Val) || self.check(&TokenKind::Def) {
+            let (rec, name, typ, expr) = self.parse_val_binding()?;
+            Ok(Input::Decl {
+                rec,
+                name,
+                typ,
+                expr,
+            })
+        } else {
+            self.expression().map(Input::Expr)
         }
-
-        expr
     }
 
-    fn parse_term(&mut self) -> Expr {
-        let mut expr = self.parse_factor();
-
-        while let Some(kind) = [TokenKind::Plus, TokenKind::Minus]
-            .iter()
-            .find(|x| self.check(x))
-        {
-            self.advance().expect("expected a token");
-            let right = self.parse_factor();
-            expr = match kind {
-                TokenKind::Plus => Expr::Add(Box::new(expr), Box::new(right)),
-                TokenKind::Minus => Expr::Sub(Box::new(expr), Box::new(right)),
-                _ => panic!("expected Plus or Minus"), // todo! error handling
-            };
-        }
+    fn expression(&mut self) -> ParseResult<Expr> {
+        self.parse_binary(0)
+    }
 
+    /// Parses an expression whose infix operators bind at least as tightly as `min_precedence`,
+    /// following the precedences and associativities in `INFIX`.
+    fn parse_binary(&mut self, min_precedence: u8) -> ParseResult<Expr> {
+        let depth = self.depth;
+        let expr = self
+            .nest()
+            .and_then(|()| stack::grow(|| self.parse_operators(min_precedence)));
+        self.depth = depth;
         expr
     }
 
-    fn parse_factor(&mut self) -> Expr {
-        let mut expr = self.parse_unary();
-
-        while let Some(kind) = [TokenKind::Slash, TokenKind::Star]
-            .iter()
-            .find(|x| self.check(x))
-        {
-            self.advance().expect("expected a token");
-            let right = self.parse_unary();
-            expr = match kind {
-                TokenKind::Slash => Expr::Div(Box::new(expr), Box::new(right)),
-                TokenKind::Star => Expr::Mul(Box::new(expr), Box::new(right)),
-                _ => panic!("expected Slash or Star"), // todo! error handling
-            };
+    fn parse_operators(&mut self, min_precedence: u8) -> ParseResult<Expr> {
+        let mut expr = self.parse_unary()?;
+
+        while let Some(op) = infix(&self.peek().kind).filter(|op| op.precedence >= min_precedence) {
+            self.advance().expect("expected an operator");
+            // The right operand of a left-associative operator stops at an operator of the same precedence.
+            let right = self.parse_binary(match op.assoc {
+                Assoc::Left => op.precedence + 1,
+                Assoc::Right => op.precedence,
+            })?;
+            let span = expr.span.to(right.span);
+            expr = Expr::new((op.build)(Box::new(expr), Box::new(right)), span);
         }
-        expr
-    }
 
-    fn parse_unary(&mut self) -> Expr {
-        let next_token = match self.peek() {
-            Some(tok) => tok,
-            None => return self.parse_postfix_unary(),
-        };
+        Ok(expr)
+    }
 
-        match next_token.kind {
+    fn parse_unary(&mut self) -> ParseResult<Expr> {
+        match self.peek().kind {
             TokenKind::Bang => {
-                // unsupported
-                todo!()
+                let bang = self.advance().expect("expected a Bang").span;
+                let right = self.parse_binary(PREFIX_PRECEDENCE)?;
+                let span = bang.to(right.span);
+                return Ok(Expr::new(ExprKind::Not(Box::new(right)), span));
             }
             TokenKind::Minus => {
-                self.advance().expect("expected a Minus");
-                let right = self.parse_unary();
+                let minus = self.advance().expect("expected a Minus").span;
+                let literal = self.check(&TokenKind::Literal);
+                self.negated = literal;
+                let right = self.parse_binary(PREFIX_PRECEDENCE);
+                self.negated = false;
+                let right = right?;
+                let span = minus.to(right.span);
+                // A literal right after `-` is folded into a negative literal, e.g. `-1.5`,
+                // since `-` only negates integers and `-9223372036854775808` only fits when folded.
+                // The literal stays the operand of `**` and of application, which bind more tightly.
+                if literal {
+                    match right.kind {
+                        ExprKind::Num(n) => {
+                            return Ok(Expr::new(ExprKind::Num(n.wrapping_neg()), span))
+                        }
+                        ExprKind::Float(x) => return Ok(Expr::new(ExprKind::Float(-x), span)),
+                        _ => (),
+                    }
+                }
                 // desugaring
-                return Expr::Sub(Box::new(Expr::Num(0)), Box::new(right));
+                let zero = Expr::new(ExprKind::Num(0), minus);
+                return Ok(Expr::new(
+                    ExprKind::Sub(Box::new(zero), Box::new(right)),
+                    span,
+                ));
+            }
+            TokenKind::MinusDot => {
+                let minus = self.advance().expect("expected a MinusDot").span;
+                let right = self.parse_binary(PREFIX_PRECEDENCE)?;
+                let span = minus.to(right.span);
+                return Ok(Expr::new(ExprKind::FNeg(Box::new(right)), span));
             }
             _ => (),
         }
@@ -150,133 +408,370 @@ impl Parser {
         self.parse_postfix_unary()
     }
 
-    fn parse_postfix_unary(&mut self) -> Expr {
-        let mut left = self.parse_primary();
+    fn parse_postfix_unary(&mut self) -> ParseResult<Expr> {
+        let mut left = self.parse_primary()?;
 
-        loop {
-            let next_token = match self.peek() {
-                Some(tok) => tok,
-                None => return left,
-            };
-
-            match next_token.kind {
-                TokenKind::LeftParen => {
-                    self.advance().expect("expected LeftParen");
-                    let expr = self.expression();
-                    self.consume(&TokenKind::RightParen, "Expected ')' after expression.");
-                    left = Expr::App(Box::new(left), Box::new(expr));
-                }
-                _ => {
-                    break;
-                }
+        while self.check(&TokenKind::LeftParen) {
+            self.advance().expect("expected LeftParen");
+            let mut args = vec![self.expression()?];
+            while self.check(&TokenKind::Comma) {
+                self.advance().expect("expected Comma");
+                args.push(self.expression()?);
             }
+            let right_paren = self.consume(&TokenKind::RightParen, "')' after arguments")?;
+            let span = left.span.to(right_paren.span);
+            left = Expr::new(ExprKind::App(Box::new(left), args), span);
         }
 
-        left
+        Ok(left)
     }
 
-    fn parse_primary(&mut self) -> Expr {
-        match &self.peek().expect("expected a token").kind {
+    fn parse_primary(&mut self) -> ParseResult<Expr> {
+        match &self.peek().kind {
             TokenKind::Literal => {
-                match self
-                    .advance()
-                    .expect("expected a Literal")
-                    .literal
-                    .expect("expected a literal")
-                {
-                    Literal::Str(x) => Expr::Id(x),
-                    Literal::Num(n) => Expr::Num(n),
-                    Literal::Float(_) => {
-                        // unsupported
-                        todo!()
+                let negated = std::mem::take(&mut self.negated);
+                let token = self.advance().expect("expected a Literal");
+                let folded = negated
+                    && !matches!(self.peek().kind, TokenKind::StarStar | TokenKind::LeftParen);
+                let kind = match token.literal.expect("expected a literal") {
+                    Literal::Str(s) => ExprKind::Str(s),
+                    // `9223372036854775808`, scanned as `isize::MIN`, is only that when `-` is folded into it.
+                    Literal::Num(isize::MIN) if !folded => {
+                        return Err(self.report(ParseError {
+                            expected: "a number that fits in Num".to_string(),
+                            found: token.kind,
+                            lexeme: token.lexeme,
+                            span: token.span,
+                        }))
                     }
-                }
+                    Literal::Num(n) => ExprKind::Num(n),
+                    Literal::Float(x) => ExprKind::Float(x),
+                };
+                Ok(Expr::new(kind, token.span))
+            }
+            TokenKind::True | TokenKind::False => {
+                let token = self.advance().expect("expected True or False");
+                let kind = ExprKind::Bool(token.kind == TokenKind::True);
+                Ok(Expr::new(kind, token.span))
             }
             TokenKind::LeftParen => {
-                self.advance().expect("expected LeftParen");
-                let expr = self.expression();
-                self.consume(&TokenKind::RightParen, "Expected ')' after expression.");
-                expr
+                let left_paren = self.advance().expect("expected LeftParen");
+                let mut expr = self.expression()?;
+                if self.check(&TokenKind::Colon) {
+                    self.advance().expect("expected Colon");
+                    let typ = self.parse_type()?;
+                    let span = expr.span;
+                    expr = Expr::new(ExprKind::Ascribe(Box::new(expr), typ), span);
+                }
+                let right_paren = self.consume(&TokenKind::RightParen, "')' after expression")?;
+                expr.span = left_paren.span.to(right_paren.span);
+                Ok(expr)
             }
 
+            TokenKind::If => self.parse_if(),
+
             TokenKind::LeftBrace => {
-                self.advance().expect("expected LeftBrace");
-                if self.check(&TokenKind::Var) {
-                    panic!("'var' is unsupported");
+                let left_brace = self.advance().expect("expected LeftBrace").span;
+                if self.check(&TokenKind::Val) || self.check(&TokenKind::Def) {
+                    self.parse_val(left_brace)
+                } else {
+                    self.parse_fun(left_brace)
                 }
+            }
 
-                if self.check(&TokenKind::Val) {
-                    // { val x = 100; expr }
-                    self.consume(&TokenKind::Val, "expected 'val'");
-                    let ident = self.advance().expect("expected identifier");
-                    if ident.kind != TokenKind::Ident {
-                        panic!("not an identifier");
-                    }
-                    let ident = ident.lexeme;
+            TokenKind::Ident => {
+                let ident = self.advance().expect("expected Ident");
+                Ok(Expr::new(ExprKind::Id(ident.lexeme), ident.span))
+            }
 
-                    self.consume(&TokenKind::Equals, "expected '='");
-                    let expr = self.expression();
+            _ => Err(self.error("expression")),
+        }
+    }
 
-                    self.consume(&TokenKind::Semicolon, "expected ';'");
-                    let body = self.expression();
+    /// Parses `if (cond) { then } else { otherwise }`, where the `else` branch can be another `if`.
+    fn parse_if(&mut self) -> ParseResult<Expr> {
+        let if_span = self.consume(&TokenKind::If, "'if'")?.span;
+        self.consume(&TokenKind::LeftParen, "'(' after 'if'")?;
+        let cond = self.expression()?;
+        self.consume(&TokenKind::RightParen, "')' after condition")?;
+        let then = self.parse_branch()?;
+
+        self.consume(&TokenKind::Else, "'else' after branch")?;
+        let otherwise = if self.check(&TokenKind::If) {
+            self.parse_if()?
+        } else {
+            self.parse_branch()?
+        };
 
-                    self.consume(&TokenKind::RightBrace, "Expected '}' after expression.");
+        let span = if_span.to(otherwise.span);
+        Ok(Expr::new(
+            ExprKind::If(Box::new(cond), Box::new(then), Box::new(otherwise)),
+            span,
+        ))
+    }
 
-                    Expr::Val(ident, omit_type(), Box::new(expr), Box::new(body))
-                } else {
-                    // First-class functions
-                    // { x => 100 }
-                    let ident = self.advance().expect("expected identifier");
-                    if ident.kind != TokenKind::Ident {
-                        panic!("not an identifier");
-                    }
-                    let ident = ident.lexeme;
+    /// Parses a branch `{ expr }` of an `if`.
+    fn parse_branch(&mut self) -> ParseResult<Expr> {
+        let left_brace = self
+            .consume(&TokenKind::LeftBrace, "'{' before branch")?
+            .span;
+        let expr = self.expression();
+        let right_brace = self.consume_block_end()?;
+
+        let mut expr = expr?;
+        expr.span = left_brace.to(right_brace.span);
+        Ok(expr)
+    }
 
-                    // consume =>
-                    self.consume(&TokenKind::Equals, "expected '='");
-                    self.consume(&TokenKind::Greater, "expected '>'");
+    /// Parses the rest of `{ val x = 100; expr }` or `{ def f = {n => ...}; expr }` after the `{`.
+    fn parse_val(&mut self, left_brace: Span) -> ParseResult<Expr> {
+        let binding = self.parse_val_binding();
+        if self
+            .consume(&TokenKind::Semicolon, "';' after value")
+            .is_err()
+        {
+            self.skip_to(&TokenKind::Semicolon);
+        }
 
-                    let expr = self.expression();
-                    self.consume(&TokenKind::RightBrace, "Expected '}' after expression.");
+        let body = self.expression();
+        let right_brace = self.consume_block_end()?;
 
-                    Expr::Fun(ident, omit_type(), Box::new(expr))
-                }
+        let (rec, ident, typ, expr) = binding?;
+        let kind = if rec {
+            ExprKind::Rec(ident, typ, Box::new(expr), Box::new(body?))
+        } else {
+            ExprKind::Val(ident, typ, Box::new(expr), Box::new(body?))
+        };
+        Ok(Expr::new(kind, left_brace.to(right_brace.span)))
+    }
+
+    /// Parses `val x = 100` or `def f = {n => ...}`, returning whether the binding is recursive.
+    ///
+    /// The variable can be annotated with its type, as in `val x: Num = 100`.
+    fn parse_val_binding(&mut self) -> ParseResult<(bool, String, Type, Expr)> {
+        let rec = self.advance().expect("expected Val or Def").kind == TokenKind::Def;
+        let ident = self.consume(&TokenKind::Ident, "variable name")?;
+        let typ = self.parse_annotation()?;
+        self.consume(&TokenKind::Equals, "'=' after variable name")?;
+
+        let start = self.peek();
+        let (found, lexeme, span) = (start.kind.clone(), start.lexeme.clone(), start.span);
+        let expr = self.expression()?;
+        // Only a function can refer to itself before it is evaluated.
+        if rec && !matches!(expr.kind, ExprKind::Fun(..)) {
+            return Err(self.report(ParseError {
+                expected: "function after '='".to_string(),
+                found,
+                lexeme,
+                span,
+            }));
+        }
+        Ok((rec, ident.lexeme, typ, expr))
+    }
+
+    /// Parses the rest of a first-class function `{ x, y => 100 }` after the `{`.
+    fn parse_fun(&mut self, left_brace: Span) -> ParseResult<Expr> {
+        let params = match self.parse_params() {
+            Ok(params) => params,
+            Err(error) => {
+                self.skip_to(&TokenKind::RightBrace);
+                return Err(error);
             }
+        };
 
-            TokenKind::Ident => {
-                let ident = self.advance().expect("expected Ident").lexeme;
-                Expr::Id(ident)
+        let body = self.expression();
+        let right_brace = self.consume_block_end()?;
+
+        Ok(Expr::new(
+            ExprKind::Fun(params, Box::new(body?)),
+            left_brace.to(right_brace.span),
+        ))
+    }
+
+    /// Parses `x, y =>`, where each parameter can be annotated as `x: Num` or `(x: Num -> Num)`.
+    fn parse_params(&mut self) -> ParseResult<Vec<(String, Type)>> {
+        let mut params = vec![];
+        self.parse_param(&mut params, "parameter name or 'val'")?;
+        while self.check(&TokenKind::Comma) {
+            self.advance().expect("expected Comma");
+            self.parse_param(&mut params, "parameter name")?;
+        }
+
+        self.consume(&TokenKind::FatArrow, "'=>' after parameters")?;
+
+        Ok(params)
+    }
+
+    /// Parses a parameter into `params`, which must not have one with the same name.
+    fn parse_param(&mut self, params: &mut Vec<(String, Type)>, expected: &str) -> ParseResult<()> {
+        let (ident, typ) = if self.check(&TokenKind::LeftParen) {
+            self.advance().expect("expected LeftParen");
+            let ident = self.consume(&TokenKind::Ident, "parameter name")?;
+            self.consume(&TokenKind::Colon, "':' after parameter name")?;
+            let typ = self.parse_type()?;
+            self.consume(&TokenKind::RightParen, "')' after parameter type")?;
+            (ident, typ)
+        } else {
+            let ident = self.consume(&TokenKind::Ident, expected)?;
+            (ident, self.parse_annotation()?)
+        };
+
+        if params.iter().any(|(name, _)| *name == ident.lexeme) {
+            return Err(self.report(ParseError {
+                expected: "a name different from the other parameters".to_string(),
+                found: ident.kind,
+                lexeme: ident.lexeme,
+                span: ident.span,
+            }));
+        }
+        params.push((ident.lexeme, typ));
+        Ok(())
+    }
+
+    /// Parses an optional `: Type`, returning a fresh type variable if it is omitted.
+    fn parse_annotation(&mut self) -> ParseResult<Type> {
+        if self.check(&TokenKind::Colon) {
+            self.advance().expect("expected Colon");
+            self.parse_type()
+        } else {
+            Ok(omit_type())
+        }
+    }
+
+    /// Parses a type such as `Num`, `Num -> Bool` or `(Num, Num) -> Num`, where `->` is right-associative.
+    fn parse_type(&mut self) -> ParseResult<Type> {
+        let depth = self.depth;
+        let typ = self
+            .nest()
+            .and_then(|()| stack::grow(|| self.parse_fun_type()));
+        self.depth = depth;
+        typ
+    }
+
+    fn parse_fun_type(&mut self) -> ParseResult<Type> {
+        let mut params = if self.check(&TokenKind::LeftParen) {
+            self.advance().expect("expected LeftParen");
+            let mut types = vec![self.parse_type()?];
+            while self.check(&TokenKind::Comma) {
+                self.advance().expect("expected Comma");
+                types.push(self.parse_type()?);
             }
+            self.consume(&TokenKind::RightParen, "')' after type")?;
+            types
+        } else {
+            vec![self.parse_type_name()?]
+        };
 
-            x => {
-                // todo! error handling
-                panic!("unexpected token {:?}", x)
+        if !self.check(&TokenKind::Arrow) {
+            // A single type in parentheses is only grouped.
+            if params.len() == 1 {
+                return Ok(params.remove(0));
             }
+            return Err(self.error("'->' after parameter types"));
         }
+        self.advance().expect("expected Arrow");
+        Ok(Type::Fun(params, Box::new(self.parse_type()?)))
     }
 
-    fn consume(&mut self, kind: &TokenKind, message: &str) -> Token {
+    fn parse_type_name(&mut self) -> ParseResult<Type> {
+        let next = self.peek();
+        let typ = match (&next.kind, next.lexeme.as_str()) {
+            (TokenKind::Ident, "Num") => Type::Num,
+            (TokenKind::Ident, "Float") => Type::Float,
+            (TokenKind::Ident, "Bool") => Type::Bool,
+            (TokenKind::Ident, "Str") => Type::Str,
+            _ => return Err(self.error("type")),
+        };
+        self.advance().expect("expected a type name");
+        Ok(typ)
+    }
+
+    /// Goes one level deeper into the expression or type being parsed,
+    /// failing at the next token if that is deeper than `MAX_NESTING`.
+    fn nest(&mut self) -> ParseResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(self.error(&format!("at most {} levels of nesting", MAX_NESTING)));
+        }
+        Ok(())
+    }
+
+    /// Consumes the `}` closing a block, skipping whatever is left of the block after an error.
+    fn consume_block_end(&mut self) -> ParseResult<Token> {
+        let right_brace = self.consume(&TokenKind::RightBrace, "'}' after expression");
+        if right_brace.is_err() {
+            self.skip_to(&TokenKind::RightBrace);
+        }
+        right_brace
+    }
+
+    /// Discards tokens up to and including the next `kind` in the current block, and returns whether it was found.
+    ///
+    /// Nested parentheses and braces are skipped as a whole,
+    /// and the `}` closing the current block is only discarded if `kind` is `RightBrace`.
+    fn skip_to(&mut self, kind: &TokenKind) -> bool {
+        let mut depth = 0usize;
+        while !self.is_at_end() {
+            let next = &self.peek().kind;
+            if depth == 0 && next == kind {
+                let _ = self.consume(kind, "");
+                return true;
+            }
+            match next {
+                TokenKind::LeftParen | TokenKind::LeftBrace => depth += 1,
+                TokenKind::RightParen | TokenKind::RightBrace if depth > 0 => depth -= 1,
+                TokenKind::RightBrace => return false,
+                _ => (),
+            }
+            self.advance().expect("expected a token");
+        }
+        false
+    }
+
+    /// Discards the rest of a top-level input up to and including the next `;`.
+    fn skip_input(&mut self) {
+        while !self.skip_to(&TokenKind::Semicolon) && !self.is_at_end() {
+            // A `}` without a matching `{`.
+            self.advance().expect("expected RightBrace");
+        }
+    }
+
+    fn consume(&mut self, kind: &TokenKind, expected: &str) -> ParseResult<Token> {
         if self.check(kind) {
-            return self.advance().expect("expected a token");
+            if matches!(kind, TokenKind::Semicolon | TokenKind::RightBrace) {
+                self.panic_mode = false;
+            }
+            return Ok(self.advance().expect("expected a token"));
+        };
+        Err(self.error(expected))
+    }
+
+    /// Records that `expected` was expected instead of the next token.
+    fn error(&mut self, expected: &str) -> ErrorReported {
+        let found = self.peek();
+        let error = ParseError {
+            expected: expected.to_string(),
+            found: found.kind.clone(),
+            lexeme: found.lexeme.clone(),
+            span: found.span,
         };
-        // todo! proper error handling
-        panic!("{} {}", self.peek().expect("expected a token"), message);
+        self.report(error)
     }
 
-    // fn take_if(&mut self, types: &[TokenKind]) -> bool {
-    //     if types.iter().any(|x| self.check(x)) {
-    //         self.advance().expect("expected a token");
-    //         true
-    //     } else {
-    //         false
-    //     }
-    // }
+    /// Records `error` unless an earlier error is still being recovered from.
+    fn report(&mut self, error: ParseError) -> ErrorReported {
+        if !self.panic_mode {
+            self.panic_mode = true;
+            self.errors.push(error);
+        }
+        ErrorReported
+    }
 
     fn check(&mut self, kind: &TokenKind) -> bool {
         if self.is_at_end() {
             false
         } else {
-            self.peek().expect("expected a token").kind == *kind
+            self.peek().kind == *kind
         }
     }
 
@@ -285,10 +780,167 @@ impl Parser {
     }
 
     fn is_at_end(&mut self) -> bool {
-        self.peek().expect("expected a token").kind == TokenKind::Eof
+        self.peek().kind == TokenKind::Eof
+    }
+
+    // The scanner always ends the tokens with `Eof`, which is never consumed.
+    fn peek(&mut self) -> &Token {
+        self.tokens.peek().expect("expected a token")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParseError, Parser};
+    use crate::daruscript_v1::scanner::{Scanner, TokenKind};
+    use crate::daruscript_v1::span::Span;
+
+    fn parse_errors(source: &str) -> Vec<ParseError> {
+        let tokens = Scanner::new(source.to_string()).scan().unwrap();
+        Parser::new(tokens)
+            .parse()
+            .expect_err("expected syntax errors")
+    }
+
+    fn parse(source: &str) -> String {
+        let tokens = Scanner::new(source.to_string()).scan().unwrap();
+        Parser::new(tokens).parse().unwrap().to_string()
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse("1 + 2 * 3 == 7 and true"),
+            "And(Eq(Add(Num(1), Mul(Num(2), Num(3))), Num(7)), Bool(true))"
+        );
+        assert_eq!(parse("1 - 2 - 3"), "Sub(Sub(Num(1), Num(2)), Num(3))");
+        assert_eq!(parse("2 ** 3 ** 2"), "Pow(Num(2), Pow(Num(3), Num(2)))");
+        assert_eq!(
+            parse("-2 ** 2 * 3"),
+            "Mul(Sub(Num(0), Pow(Num(2), Num(2))), Num(3))"
+        );
+        assert_eq!(parse("-2 * 3 - -1"), "Sub(Mul(Num(-2), Num(3)), Num(-1))");
+        assert_eq!(
+            parse("-.x *. 2.0 -. -.1.5"),
+            "FSub(FMul(FNeg(Id(\"x\")), Float(2.0)), FNeg(Float(1.5)))"
+        );
+        assert_eq!(parse("!a or b"), "Or(Not(Id(\"a\")), Id(\"b\"))");
+        assert_eq!(
+            parse("7 % 4 + 1 |> f |> g"),
+            "App(Id(\"g\"), App(Id(\"f\"), Add(Mod(Num(7), Num(4)), Num(1))))"
+        );
+    }
+
+    #[test]
+    fn nesting() {
+        let parens = format!("{}1{}", "(".repeat(300), ")".repeat(300));
+        assert_eq!(
+            parse_errors(&parens)[0].expected,
+            "at most 256 levels of nesting"
+        );
+        let funs = format!("{}1{}", "{x => ".repeat(300), "}".repeat(300));
+        assert_eq!(
+            parse_errors(&funs)[0].expected,
+            "at most 256 levels of nesting"
+        );
+        // The right operand of a right-associative operator is nested, but a left-associative chain is not.
+        assert_eq!(
+            parse_errors(&vec!["1"; 300].join(" ** "))[0].expected,
+            "at most 256 levels of nesting"
+        );
+        assert!(parse(&vec!["1"; 5000].join(" + ")).starts_with("Add(Add("));
+        assert!(parse(&format!("f{}", "(1)".repeat(5000))).starts_with("App(App("));
+    }
+
+    #[test]
+    fn unexpected_token() {
+        assert_eq!(
+            parse_errors("(1 + 2"),
+            vec![ParseError {
+                expected: "')' after expression".to_string(),
+                found: TokenKind::Eof,
+                lexeme: "".to_string(),
+                span: Span::new(6, 6, 1, 7),
+            }]
+        );
+        assert_eq!(
+            parse_errors("{x => x} 3"),
+            vec![ParseError {
+                expected: "end of input".to_string(),
+                found: TokenKind::Literal,
+                lexeme: "3".to_string(),
+                span: Span::new(9, 10, 1, 10),
+            }]
+        );
+        assert_eq!(parse_errors("")[0].expected, "expression");
+        assert_eq!(
+            parse_errors("{var x = 1; x}")[0].expected,
+            "parameter name or 'val'"
+        );
+    }
+
+    #[test]
+    fn recovery() {
+        let errors = parse_errors("{val x = 1 + ; {val y = 2 3; y * }}");
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.expected.as_str(), error.lexeme.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("expression", ";"),
+                ("';' after value", "3"),
+                ("expression", "}"),
+            ]
+        );
+
+        // Errors caused by an earlier error are not reported.
+        assert_eq!(parse_errors("{val x = 1 + }").len(), 1);
+        assert_eq!(parse_errors("{1 => 1} + {y => (y +}").len(), 2);
+    }
+
+    #[test]
+    fn type_syntax() {
+        assert_eq!(parse_errors("{val x: Text = 1; x}")[0].expected, "type");
+        assert_eq!(
+            parse_errors("{(x) => x}")[0].expected,
+            "':' after parameter name"
+        );
+        assert_eq!(
+            parse_errors("{val f: (Num, Num) = 1; f}")[0].expected,
+            "'->' after parameter types"
+        );
+        assert_eq!(parse_errors("(1 : Num -> )")[0].expected, "type");
+    }
+
+    #[test]
+    fn duplicate_params() {
+        assert_eq!(
+            parse_errors("{x, (x: Num) => x}"),
+            vec![ParseError {
+                expected: "a name different from the other parameters".to_string(),
+                found: TokenKind::Ident,
+                lexeme: "x".to_string(),
+                span: Span::new(5, 6, 1, 6),
+            }]
+        );
+        assert_eq!(
+            parse_errors("{a, b, a => a}")[0].span,
+            Span::new(7, 8, 1, 8)
+        );
+        assert!(parse("{x => {x => x}}").starts_with("Fun("));
     }
 
-    fn peek(&mut self) -> Option<&Token> {
-        self.tokens.peek()
+    #[test]
+    fn split_arrows() {
+        assert_eq!(
+            parse_errors("{x = > 1}")[0].expected,
+            "'=>' after parameters"
+        );
+        assert_eq!(
+            parse_errors("{val f: Num - > Num = {x => x}; f}")[0].expected,
+            "'=' after variable name"
+        );
     }
 }
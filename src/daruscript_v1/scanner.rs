@@ -1,20 +1,76 @@
+use super::diagnostic::{Diagnostic, Label};
+use super::span::Span;
 use std::fmt;
+use std::num::IntErrorKind;
+use unicode_ident::{is_xid_continue, is_xid_start};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexErrorKind {
+    /// A character that does not start any token.
+    UnexpectedChar(char),
+    /// A string literal that is still open at the end of the source, reported at its opening `"`.
+    UnterminatedString,
+    /// A block comment that is still open at the end of the source, reported at its opening `/*`.
+    UnterminatedComment,
+    /// A `\` in a string literal that does not start a known escape sequence, e.g. `\q` or `\u{110000}`.
+    InvalidEscape(String),
+    /// A number literal with a digit outside its radix or without any digits, e.g. `0b12` or `0x`.
+    MalformedNumber,
+    /// A number literal too large for `Num`, or a float literal too large for `Float`.
+    NumberOverflow,
+}
 
-fn error(line: usize, message: String) {
-    report(line, "", message);
+/// An error found while scanning, with the span of the offending lexeme.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
 }
 
-fn report(line: usize, err_where: &str, message: String) {
-    eprintln!("[line {line} ] Error{err_where}: {message}");
+impl LexError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let (message, label) = match &self.kind {
+            LexErrorKind::UnexpectedChar(c) => (
+                format!("Unexpected character {:?}.", c),
+                "unexpected character",
+            ),
+            LexErrorKind::UnterminatedString => {
+                ("Unterminated string.".to_string(), "string starts here")
+            }
+            LexErrorKind::UnterminatedComment => (
+                "Unterminated block comment.".to_string(),
+                "comment starts here",
+            ),
+            LexErrorKind::InvalidEscape(escape) => (
+                format!("Invalid escape sequence '{}'.", escape),
+                "invalid escape sequence",
+            ),
+            LexErrorKind::MalformedNumber => {
+                ("Malformed number literal.".to_string(), "malformed number")
+            }
+            LexErrorKind::NumberOverflow => (
+                "Number literal is too large.".to_string(),
+                "does not fit in a number",
+            ),
+        };
+        Diagnostic::new("Error", message, Label::new(self.span, label))
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.diagnostic())
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
     // Single-character tokens
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -22,6 +78,7 @@ pub enum TokenKind {
     Semicolon,
     Star,
     Slash,
+    Percent,
 
     // One or two character tokens
     Bang,
@@ -32,6 +89,24 @@ pub enum TokenKind {
     GreaterOrEquals,
     Less,
     LessOrEquals,
+    PlusPlus,
+    StarStar,
+    // `x |> f`, which applies `f` to `x`
+    Pipe,
+    // `=>` between the parameters and the body of a function
+    FatArrow,
+    // `->` between the parameter and return types of a function type
+    Arrow,
+
+    // Floating-point operators, which are the integer ones followed by '.'
+    PlusDot,
+    MinusDot,
+    StarDot,
+    SlashDot,
+    GreaterDot,
+    GreaterOrEqualsDot,
+    LessDot,
+    LessOrEqualsDot,
 
     // Literals
     Ident,
@@ -39,6 +114,7 @@ pub enum TokenKind {
 
     // Keywords
     And,
+    Def,
     Else,
     False,
     Fun,
@@ -53,18 +129,45 @@ pub enum TokenKind {
     Val,
     While,
 
-    // LineComment,
-    // BlockComment { terminated: bool },
+    // Trivia, only scanned by a lossless scanner
+    Whitespace,
+    LineComment,
+    BlockComment { terminated: bool },
+    // A lexeme with a lexical error, e.g. an unexpected character or a malformed number,
+    // only scanned by a lossless scanner
+    Error,
+
     Eof,
 }
 
 use TokenKind::*;
 
+/// The reserved words and the tokens they are scanned as.
+pub const KEYWORDS: &[(&str, TokenKind)] = &[
+    ("and", And),
+    ("def", Def),
+    ("else", Else),
+    ("false", False),
+    ("for", For),
+    ("fun", Fun),
+    ("if", If),
+    ("nil", Nil),
+    ("or", Or),
+    ("print", Print),
+    ("return", Return),
+    ("true", True),
+    ("var", Var),
+    ("while", While),
+    ("val", Val),
+];
+
 #[derive(Debug)]
 pub enum Literal {
     Str(String),
+    /// An integer literal. `9223372036854775808`, which only fits when negated,
+    /// is scanned as `isize::MIN`; the parser only accepts it after a prefix `-`.
     Num(isize),
-    Float(f32),
+    Float(f64),
 }
 
 #[derive(Debug)]
@@ -72,7 +175,7 @@ pub struct Token {
     pub kind: TokenKind,
     pub lexeme: String,
     pub literal: Option<Literal>,
-    pub line: usize,
+    pub span: Span,
 }
 
 impl fmt::Display for Token {
@@ -85,9 +188,17 @@ impl fmt::Display for Token {
 pub struct Scanner {
     source: String,
     tokens: Vec<Token>,
+    errors: Vec<LexError>,
     start: usize,
     current: usize,
     line: usize,
+    // Byte offset of the first character of the current line.
+    line_start: usize,
+    // Line and column of `start`.
+    start_line: usize,
+    start_col: usize,
+    // Whether whitespace, comments and lexemes with errors are scanned as tokens.
+    trivia: bool,
 }
 
 impl Scanner {
@@ -95,30 +206,52 @@ impl Scanner {
         Scanner {
             source,
             tokens: vec![],
+            errors: vec![],
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_col: 1,
+            trivia: false,
+        }
+    }
+
+    /// Scans the whole source.
+    ///
+    /// Scanning carries on past a lexical error, so every error in the source is reported at once.
+    pub fn scan(mut self) -> Result<Vec<Token>, Vec<LexError>> {
+        self.scan_all();
+        if self.errors.is_empty() {
+            Ok(self.tokens)
+        } else {
+            Err(self.errors)
         }
     }
 
-    pub fn scan(mut self) -> Vec<Token> {
+    /// Scans the whole source losslessly: whitespace and comments are scanned as `Whitespace`,
+    /// `LineComment` and `BlockComment` tokens, so the lexemes of the tokens add up to the source.
+    ///
+    /// The tokens are returned along with the errors, so that source that is still being written,
+    /// e.g. with an unclosed `/*`, can be worked with. A lexeme that is not a token is scanned as `Error`.
+    /// This is meant for tools such as formatters; the parser expects the tokens without trivia.
+    pub fn scan_lossless(mut self) -> (Vec<Token>, Vec<LexError>) {
+        self.trivia = true;
+        self.scan_all();
+        (self.tokens, self.errors)
+    }
+
+    fn scan_all(&mut self) {
         while !self.is_at_end() {
             // At the beginning of the next lexeme.
-            self.start = self.current;
+            self.start_lexeme();
             self.scan_token();
         }
 
-        self.tokens.push(Token {
-            kind: Eof,
-            lexeme: "".to_string(),
-            literal: None,
-            line: self.line,
-        });
-
-        self.tokens
+        self.start_lexeme();
+        self.add_token(Eof, None);
     }
 
-    // todo! return Error https://craftinginterpreters.com/scanning.html#lexical-errors
     fn scan_token(&mut self) {
         let c = self.advance();
         match c {
@@ -127,12 +260,45 @@ impl Scanner {
             ')' => self.add_token(RightParen, None),
             '{' => self.add_token(LeftBrace, None),
             '}' => self.add_token(RightBrace, None),
+            ':' => self.add_token(Colon, None),
             ',' => self.add_token(Comma, None),
             '.' => self.add_token(Dot, None),
-            '-' => self.add_token(Minus, None),
-            '+' => self.add_token(Plus, None),
+            '-' => {
+                if self.take_if('.') {
+                    self.add_token(MinusDot, None);
+                } else if self.take_if('>') {
+                    self.add_token(Arrow, None);
+                } else {
+                    self.add_token(Minus, None);
+                }
+            }
+            '+' => {
+                if self.take_if('+') {
+                    self.add_token(PlusPlus, None);
+                } else if self.take_if('.') {
+                    self.add_token(PlusDot, None);
+                } else {
+                    self.add_token(Plus, None);
+                }
+            }
             ';' => self.add_token(Semicolon, None),
-            '*' => self.add_token(Star, None),
+            '%' => self.add_token(Percent, None),
+            '|' => {
+                if self.take_if('>') {
+                    self.add_token(Pipe, None);
+                } else {
+                    self.error(LexErrorKind::UnexpectedChar('|'));
+                }
+            }
+            '*' => {
+                if self.take_if('*') {
+                    self.add_token(StarStar, None);
+                } else if self.take_if('.') {
+                    self.add_token(StarDot, None);
+                } else {
+                    self.add_token(Star, None);
+                }
+            }
             '!' => {
                 if self.take_if('=') {
                     self.add_token(BangEquals, None);
@@ -143,23 +309,31 @@ impl Scanner {
             '=' => {
                 if self.take_if('=') {
                     self.add_token(EqualsEquals, None);
+                } else if self.take_if('>') {
+                    self.add_token(FatArrow, None);
                 } else {
                     self.add_token(Equals, None);
                 }
             }
             '<' => {
-                if self.take_if('=') {
-                    self.add_token(LessOrEquals, None);
-                } else {
-                    self.add_token(Less, None);
-                }
+                let or_equals = self.take_if('=');
+                let kind = match (or_equals, self.take_if('.')) {
+                    (false, false) => Less,
+                    (true, false) => LessOrEquals,
+                    (false, true) => LessDot,
+                    (true, true) => LessOrEqualsDot,
+                };
+                self.add_token(kind, None);
             }
             '>' => {
-                if self.take_if('=') {
-                    self.add_token(GreaterOrEquals, None);
-                } else {
-                    self.add_token(Greater, None);
-                }
+                let or_equals = self.take_if('=');
+                let kind = match (or_equals, self.take_if('.')) {
+                    (false, false) => Greater,
+                    (true, false) => GreaterOrEquals,
+                    (false, true) => GreaterDot,
+                    (true, true) => GreaterOrEqualsDot,
+                };
+                self.add_token(kind, None);
             }
             // Comments
             '/' => {
@@ -167,7 +341,7 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         let _ = self.advance();
                     }
-                    // self.add_token(LineComment, None);
+                    self.add_lossless(LineComment);
                 } else if self.take_if('*') {
                     let mut depth = 1usize;
                     while !self.is_at_end() {
@@ -182,120 +356,223 @@ impl Scanner {
                                     break;
                                 }
                             }
-                            '\n' => {
-                                self.line += 1;
-                            }
+                            '\n' => self.newline(),
                             _ => (),
                         }
                     }
-                    // self.add_token(
-                    //     BlockComment {
-                    //         terminated: depth == 0,
-                    //     },
-                    //     None,
-                    // );
+                    if depth > 0 {
+                        self.error_at_start(LexErrorKind::UnterminatedComment, 2);
+                    }
+                    self.add_lossless(BlockComment {
+                        terminated: depth == 0,
+                    });
+                } else if self.take_if('.') {
+                    self.add_token(SlashDot, None);
                 } else {
                     self.add_token(Slash, None);
                 }
             }
             // Whitespace
-            ' ' => (),
-            '\r' => (),
-            '\t' => (),
-            '\n' => self.line += 1,
+            ' ' | '\r' | '\t' | '\n' => {
+                if c == '\n' {
+                    self.newline();
+                }
+                while matches!(self.peek(), ' ' | '\r' | '\t' | '\n') {
+                    if self.advance() == '\n' {
+                        self.newline();
+                    }
+                }
+                self.add_lossless(Whitespace);
+            }
             // Literals
             '"' => self.string(),
             n if n.is_ascii_digit() => {
                 self.number();
             }
-            c if c.is_ascii_alphabetic() || c == '_' => {
+            c if is_xid_start(c) || c == '_' => {
                 self.identifier();
             }
             // Unsupported character
-            _ => error(self.line, "Unexpected character.".to_string()),
+            c => self.error(LexErrorKind::UnexpectedChar(c)),
         }
     }
 
     fn identifier(&mut self) {
-        while self.peek().is_ascii_alphanumeric() {
+        while is_xid_continue(self.peek()) {
             self.advance();
         }
 
         let text = &self.source[self.start..self.current];
-        let kind = match text {
-            "and" => And,
-            "else" => Else,
-            "false" => False,
-            "for" => For,
-            "fun" => Fun,
-            "if" => If,
-            "nil" => Nil,
-            "or" => Or,
-            "print" => Print,
-            "return" => Return,
-            "true" => True,
-            "var" => Var,
-            "while" => While,
-            "val" => Val,
+        let kind = match KEYWORDS.iter().find(|(keyword, _)| *keyword == text) {
+            Some((_, kind)) => kind.clone(),
             // Not a reserved keyword
-            _ => Ident,
+            None => Ident,
         };
 
         self.add_token(kind, None);
     }
 
+    /// Scans a number literal: a decimal integer or float such as `1_000`, `2.5` or `6.02e23`,
+    /// or an integer with the prefix `0x`, `0o` or `0b`. Digits can be separated by `_`.
     fn number(&mut self) {
-        while self.peek().is_ascii_digit() {
+        let radix = match (&self.source[self.start..self.current], self.peek()) {
+            ("0", 'x') => 16,
+            ("0", 'o') => 8,
+            ("0", 'b') => 2,
+            _ => 10,
+        };
+        if radix != 10 {
             self.advance();
+            // Letters are taken too, so that a digit outside the radix is reported with the literal.
+            while is_xid_continue(self.peek()) {
+                self.advance();
+            }
+            let digits = self.source[self.start + 2..self.current].replace('_', "");
+            match usize::from_str_radix(&digits, radix) {
+                Ok(n) => self.add_integer(n),
+                Err(error) => self.error(match error.kind() {
+                    IntErrorKind::PosOverflow => LexErrorKind::NumberOverflow,
+                    _ => LexErrorKind::MalformedNumber,
+                }),
+            }
+            return;
         }
 
-        let literal = if self.peek() == '.' && self.peek_second().is_ascii_digit() {
-            // If float.
-            // Consume '.'
+        self.digits();
+        let mut float = false;
+        if self.peek() == '.' && self.peek_second().is_ascii_digit() {
+            self.advance();
+            self.digits();
+            float = true;
+        }
+        if matches!(self.peek(), 'e' | 'E') && self.exponent_follows() {
             self.advance();
-            while self.peek().is_ascii_digit() {
+            if matches!(self.peek(), '+' | '-') {
                 self.advance();
             }
+            self.digits();
+            float = true;
+        }
 
-            Literal::Float(
-                self.source[self.start..self.current]
-                    .parse()
-                    .expect("expected a float"),
-            )
+        let text = self.source[self.start..self.current].replace('_', "");
+        if float {
+            match text.parse::<f64>() {
+                Ok(x) if x.is_finite() => self.add_token(Literal, Some(Literal::Float(x))),
+                _ => self.error(LexErrorKind::NumberOverflow),
+            }
         } else {
-            // Not a float
-            Literal::Num(
-                self.source[self.start..self.current]
-                    .parse()
-                    .expect("expected an integer"),
-            )
-        };
+            match text.parse() {
+                Ok(n) => self.add_integer(n),
+                Err(_) => self.error(LexErrorKind::NumberOverflow),
+            }
+        }
+    }
 
-        self.add_token(Literal, Some(literal));
+    // Adds an integer literal, or reports it if it does not fit even when negated.
+    fn add_integer(&mut self, n: usize) {
+        match isize::try_from(n) {
+            Ok(n) => self.add_token(Literal, Some(Literal::Num(n))),
+            Err(_) if n == isize::MIN.unsigned_abs() => {
+                self.add_token(Literal, Some(Literal::Num(isize::MIN)))
+            }
+            Err(_) => self.error(LexErrorKind::NumberOverflow),
+        }
+    }
+
+    // Takes the decimal digits and separators at the current position.
+    fn digits(&mut self) {
+        while self.peek().is_ascii_digit() || self.peek() == '_' {
+            self.advance();
+        }
+    }
+
+    // Whether the `e` at the current position starts an exponent, e.g. `e5` or `e-5`.
+    fn exponent_follows(&self) -> bool {
+        let mut rest = self.source[self.current..].chars().skip(1);
+        match rest.next() {
+            Some('+' | '-') => rest.next().is_some_and(|c| c.is_ascii_digit()),
+            Some(c) => c.is_ascii_digit(),
+            None => false,
+        }
     }
 
     fn string(&mut self) {
+        let mut value = String::new();
+        // The text since the opening quote or the last escape sequence, which is copied as it is.
+        let mut chunk_start = self.start + 1;
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1
-            };
-            self.advance();
+            match self.advance() {
+                '\n' => self.newline(),
+                '\\' => {
+                    value.push_str(&self.source[chunk_start..self.current - 1]);
+                    if let Some(c) = self.escape() {
+                        value.push(c);
+                    }
+                    chunk_start = self.current;
+                }
+                _ => (),
+            }
         }
 
+        value.push_str(&self.source[chunk_start..self.current]);
         if self.is_at_end() {
-            error(self.line, "Unterminated string.".to_string());
+            self.error_at_start(LexErrorKind::UnterminatedString, 1);
+            // Like an unterminated block comment, the string so far is kept by a lossless scanner.
+            if self.trivia {
+                self.add_token(Literal, Some(Literal::Str(value)));
+            }
             return;
         }
 
         // closing '"'.
         self.advance();
 
-        // Trim quotes
-        let value = self.source[self.start + 1..self.current - 1].to_string();
         let literal = Literal::Str(value);
         self.add_token(Literal, Some(literal));
     }
 
+    /// Scans the escape sequence after a `\` in a string literal and returns the character it stands for.
+    ///
+    /// The sequences are `\n`, `\t`, `\"`, `\\` and `\u{...}` with 1 to 6 hexadecimal digits.
+    fn escape(&mut self) -> Option<char> {
+        let start = self.current - 1;
+        let (line, col) = (self.line, self.col(start));
+        if self.is_at_end() {
+            // Reported as an unterminated string.
+            return None;
+        }
+        let c = match self.advance() {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            '\n' => {
+                self.newline();
+                None
+            }
+            'u' if self.take_if('{') => {
+                let digits = self.current;
+                while self.peek().is_ascii_hexdigit() {
+                    self.advance();
+                }
+                let code = self.source[digits..self.current].to_string();
+                let closed = self.take_if('}');
+                if closed && (1..=6).contains(&code.len()) {
+                    u32::from_str_radix(&code, 16).ok().and_then(char::from_u32)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        if c.is_none() {
+            let escape = self.source[start..self.current].to_string();
+            let span = Span::new(start, self.current, line, col);
+            self.error_at(LexErrorKind::InvalidEscape(escape), span);
+        }
+        c
+    }
+
     /// Takes the current character and returns true if it is not EOF and is `c`, else returns false.
     fn take_if(&mut self, c: char) -> bool {
         if self.is_at_end() {
@@ -305,7 +582,7 @@ impl Scanner {
             return false;
         };
 
-        self.current += 1;
+        self.current += c.len_utf8();
         true
     }
 
@@ -318,39 +595,356 @@ impl Scanner {
     }
 
     fn peek_second(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            '\0'
-        } else {
-            self.nth_char(1)
-        }
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
     fn advance(&mut self) -> char {
         let c = self.current_char();
-        self.current += 1;
+        self.current += c.len_utf8();
         c
     }
 
+    fn start_lexeme(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_col = self.col(self.current);
+    }
+
+    /// The 1-based column of the byte offset `offset` in the current line, counted in characters.
+    fn col(&self, offset: usize) -> usize {
+        self.source[self.line_start..offset].chars().count() + 1
+    }
+
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    // Reports an error at the whole lexeme, which is kept as an `Error` token if the scanner is lossless.
+    fn error(&mut self, kind: LexErrorKind) {
+        self.error_at(kind, self.span());
+        self.add_lossless(Error);
+    }
+
+    // Reports an error at the first `len` bytes of the lexeme, e.g. its opening delimiter.
+    fn error_at_start(&mut self, kind: LexErrorKind, len: usize) {
+        let span = Span::new(
+            self.start,
+            self.start + len,
+            self.start_line,
+            self.start_col,
+        );
+        self.error_at(kind, span);
+    }
+
+    fn error_at(&mut self, kind: LexErrorKind, span: Span) {
+        self.errors.push(LexError { kind, span });
+    }
+
+    /// The span of the lexeme being scanned.
+    fn span(&self) -> Span {
+        Span::new(self.start, self.current, self.start_line, self.start_col)
+    }
+
+    // Adds a whitespace, comment or error token if the scanner is lossless.
+    fn add_lossless(&mut self, kind: TokenKind) {
+        if self.trivia {
+            self.add_token(kind, None);
+        }
+    }
+
     fn add_token(&mut self, kind: TokenKind, literal: Option<Literal>) {
-        // fishy
         let lexeme = self.source[self.start..self.current].to_string();
         self.tokens.push(Token {
             kind,
             lexeme,
             literal,
-            line: self.line,
+            span: self.span(),
         });
     }
 
     fn current_char(&self) -> char {
-        self.source.as_bytes()[self.current] as char
-    }
-
-    fn nth_char(&self, n: usize) -> char {
-        self.source.as_bytes()[self.current + n] as char
+        self.source[self.current..]
+            .chars()
+            .next()
+            .expect("expected a character")
     }
 
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{LexError, LexErrorKind, Literal, Scanner, TokenKind};
+    use crate::daruscript_v1::span::Span;
+
+    fn scan_errors(source: &str) -> Vec<LexError> {
+        Scanner::new(source.to_string())
+            .scan()
+            .expect_err("expected lexical errors")
+    }
+
+    #[test]
+    fn unexpected_character() {
+        assert_eq!(
+            scan_errors("1 + @"),
+            vec![LexError {
+                kind: LexErrorKind::UnexpectedChar('@'),
+                span: Span::new(4, 5, 1, 5),
+            }]
+        );
+        assert_eq!(
+            scan_errors("1 +\n  2 # 3 $"),
+            vec![
+                LexError {
+                    kind: LexErrorKind::UnexpectedChar('#'),
+                    span: Span::new(8, 9, 2, 5),
+                },
+                LexError {
+                    kind: LexErrorKind::UnexpectedChar('$'),
+                    span: Span::new(12, 13, 2, 9),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_string() {
+        assert_eq!(
+            scan_errors("1 + \"abc\ndef"),
+            vec![LexError {
+                kind: LexErrorKind::UnterminatedString,
+                span: Span::new(4, 5, 1, 5),
+            }]
+        );
+    }
+
+    #[test]
+    fn unterminated_comment() {
+        assert_eq!(
+            scan_errors("1 +\n  /* a /* b */ c\n2"),
+            vec![LexError {
+                kind: LexErrorKind::UnterminatedComment,
+                span: Span::new(6, 8, 2, 3),
+            }]
+        );
+        assert!(Scanner::new("1 /* a /* b */ c */".to_string())
+            .scan()
+            .is_ok());
+    }
+
+    #[test]
+    fn escapes() {
+        let tokens = Scanner::new(r#""a\"b\\c\nd\te\u{3042}\u{1F600}""#.to_string())
+            .scan()
+            .unwrap();
+        assert!(matches!(
+            &tokens[0].literal,
+            Some(Literal::Str(s)) if s == "a\"b\\c\nd\te\u{3042}\u{1F600}"
+        ));
+
+        assert_eq!(
+            scan_errors(r#""ok" ++ "a\qb\u{110000}\u{}""#),
+            vec![
+                LexError {
+                    kind: LexErrorKind::InvalidEscape("\\q".to_string()),
+                    span: Span::new(10, 12, 1, 11),
+                },
+                LexError {
+                    kind: LexErrorKind::InvalidEscape("\\u{110000}".to_string()),
+                    span: Span::new(13, 23, 1, 14),
+                },
+                LexError {
+                    kind: LexErrorKind::InvalidEscape("\\u{}".to_string()),
+                    span: Span::new(23, 27, 1, 24),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unicode() {
+        let tokens = Scanner::new("{val 値 = 1; 값_2 + \"やめて\"}".to_string())
+            .scan()
+            .unwrap();
+        let idents: Vec<(&str, usize)> = tokens
+            .iter()
+            .filter(|token| token.kind == TokenKind::Ident)
+            .map(|token| (token.lexeme.as_str(), token.span.col))
+            .collect();
+        assert_eq!(idents, [("値", 6), ("값_2", 13)]);
+        assert!(matches!(
+            &tokens[8].literal,
+            Some(Literal::Str(s)) if s == "やめて"
+        ));
+
+        // Columns count characters, while spans keep byte offsets.
+        assert_eq!(
+            scan_errors("名前 → 1"),
+            vec![LexError {
+                kind: LexErrorKind::UnexpectedChar('→'),
+                span: Span::new(7, 10, 1, 4),
+            }]
+        );
+    }
+
+    fn scan_literal(source: &str) -> Literal {
+        let mut tokens = Scanner::new(source.to_string()).scan().unwrap();
+        assert_eq!(tokens.len(), 2, "expected a single literal in {:?}", source);
+        tokens.remove(0).literal.expect("expected a literal")
+    }
+
+    #[test]
+    fn number_literals() {
+        for n in [0, 1, 7, 255, 1 << 40, isize::MAX] {
+            for source in [
+                format!("{}", n),
+                format!("{:#x}", n),
+                format!("{:#X}", n),
+                format!("{:#o}", n),
+                format!("{:#b}", n),
+            ] {
+                assert!(
+                    matches!(scan_literal(&source), Literal::Num(m) if m == n),
+                    "{}",
+                    source
+                );
+            }
+        }
+        for x in [0.0, 0.1, 2.5, 123.456, 6.02e23, 1.5e300, 2.5e-8, f64::MAX] {
+            let source = format!("{:?}", x);
+            assert!(
+                matches!(scan_literal(&source), Literal::Float(y) if y == x),
+                "{}",
+                source
+            );
+        }
+
+        assert!(matches!(scan_literal("1_000_000"), Literal::Num(1_000_000)));
+        assert!(matches!(scan_literal("0xff_ff"), Literal::Num(0xffff)));
+        assert!(matches!(
+            scan_literal("0b1010_1010"),
+            Literal::Num(0b1010_1010)
+        ));
+        assert!(matches!(scan_literal("1_000.5"), Literal::Float(x) if x == 1000.5));
+        assert!(matches!(scan_literal("1e3"), Literal::Float(x) if x == 1000.0));
+        assert!(matches!(scan_literal("2.5E-1"), Literal::Float(x) if x == 0.25));
+        assert!(matches!(scan_literal("1e+2"), Literal::Float(x) if x == 100.0));
+        // The parser checks that it is negated.
+        assert!(matches!(
+            scan_literal("9223372036854775808"),
+            Literal::Num(isize::MIN)
+        ));
+        assert!(matches!(
+            scan_literal("0x8000_0000_0000_0000"),
+            Literal::Num(isize::MIN)
+        ));
+    }
+
+    #[test]
+    fn number_errors() {
+        assert_eq!(
+            scan_errors("1 + 9223372036854775809"),
+            vec![LexError {
+                kind: LexErrorKind::NumberOverflow,
+                span: Span::new(4, 23, 1, 5),
+            }]
+        );
+        assert_eq!(
+            scan_errors("0x8000_0000_0000_0001 0b102 0x 0o8 1e400"),
+            vec![
+                LexError {
+                    kind: LexErrorKind::NumberOverflow,
+                    span: Span::new(0, 21, 1, 1),
+                },
+                LexError {
+                    kind: LexErrorKind::MalformedNumber,
+                    span: Span::new(22, 27, 1, 23),
+                },
+                LexError {
+                    kind: LexErrorKind::MalformedNumber,
+                    span: Span::new(28, 30, 1, 29),
+                },
+                LexError {
+                    kind: LexErrorKind::MalformedNumber,
+                    span: Span::new(31, 34, 1, 32),
+                },
+                LexError {
+                    kind: LexErrorKind::NumberOverflow,
+                    span: Span::new(35, 40, 1, 36),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn lossless() {
+        let source = "{x =>\n  // double\n  x * /* two /* nested */ */ 2}  ";
+        let (tokens, errors) = Scanner::new(source.to_string()).scan_lossless();
+        assert!(errors.is_empty());
+        let lexemes: String = tokens.iter().map(|token| token.lexeme.as_str()).collect();
+        assert_eq!(lexemes, source);
+
+        let kinds: Vec<TokenKind> = tokens.into_iter().map(|token| token.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::LeftBrace,
+                TokenKind::Ident,
+                TokenKind::Whitespace,
+                TokenKind::FatArrow,
+                TokenKind::Whitespace,
+                TokenKind::LineComment,
+                TokenKind::Whitespace,
+                TokenKind::Ident,
+                TokenKind::Whitespace,
+                TokenKind::Star,
+                TokenKind::Whitespace,
+                TokenKind::BlockComment { terminated: true },
+                TokenKind::Whitespace,
+                TokenKind::Literal,
+                TokenKind::RightBrace,
+                TokenKind::Whitespace,
+                TokenKind::Eof,
+            ]
+        );
+
+        let (tokens, errors) = Scanner::new("1 /* open".to_string()).scan_lossless();
+        assert_eq!(
+            tokens[2].kind,
+            TokenKind::BlockComment { terminated: false }
+        );
+        assert_eq!(tokens[2].lexeme, "/* open");
+        assert_eq!(errors[0].kind, LexErrorKind::UnterminatedComment);
+
+        let (tokens, errors) = Scanner::new("1 ++ \"op\\ten".to_string()).scan_lossless();
+        assert_eq!(tokens[4].kind, TokenKind::Literal);
+        assert_eq!(tokens[4].lexeme, "\"op\\ten");
+        assert!(matches!(&tokens[4].literal, Some(Literal::Str(s)) if s == "op\ten"));
+        assert_eq!(errors[0].kind, LexErrorKind::UnterminatedString);
+
+        // Lexemes with errors are kept too.
+        for source in ["1 @ 2", "0b12 + 1", "1e400 | 9223372036854775808 ++ \"a\\q"] {
+            let (tokens, errors) = Scanner::new(source.to_string()).scan_lossless();
+            assert!(!errors.is_empty());
+            let lexemes: String = tokens.iter().map(|token| token.lexeme.as_str()).collect();
+            assert_eq!(lexemes, source);
+        }
+        let (tokens, _) = Scanner::new("1 @ 2".to_string()).scan_lossless();
+        assert_eq!(tokens[2].kind, TokenKind::Error);
+        assert_eq!(tokens[2].lexeme, "@");
+
+        // Without the lossless mode, trivia is skipped.
+        let tokens = Scanner::new(source.to_string()).scan().unwrap();
+        assert_eq!(tokens.len(), 8);
+    }
+
+    #[test]
+    fn no_errors() {
+        assert!(Scanner::new("{val x = 3; x + 1}".to_string())
+            .scan()
+            .is_ok());
+    }
+}
@@ -0,0 +1,14 @@
+// Room that must be left on the stack before the next step of a recursive pass,
+// larger than the frames between two calls to `grow` even in unoptimized builds.
+const RED_ZONE: usize = 256 * 1024;
+
+// The size of each stack segment allocated by `grow`.
+const SEGMENT_SIZE: usize = 4 * 1024 * 1024;
+
+/// Runs `f`, on a new stack segment allocated on the heap if the current stack is nearly full.
+///
+/// The recursive passes over the syntax tree call this at every level,
+/// so that deeply nested programs cannot overflow the native stack of the host thread.
+pub fn grow<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, SEGMENT_SIZE, f)
+}
@@ -1,90 +1,566 @@
+use core::fmt;
 use std::{cell::RefCell, rc::Rc};
 
+use super::builtin::Builtin;
+use super::diagnostic::{Diagnostic, Label};
+use super::span::Span;
+use super::stack;
 use super::typed::*;
 
-pub fn type_check(expr: Expr, mut tenv: TEnv) -> Type {
-    match expr {
-        Expr::Num(_) => Type::Num,
-        Expr::Add(left, right)
-        | Expr::Sub(left, right)
-        | Expr::Mul(left, right)
-        | Expr::Div(left, right) => {
-            let lt = type_check(*left, tenv.clone());
-            let rt = type_check(*right, tenv.clone());
-            unify(lt, Type::Num);
-            unify(rt, Type::Num);
-            Type::Num
-        }
-        Expr::Val(name, typ, expr, body) => {
-            let et = type_check(*expr, tenv.clone());
-            unify(et, typ.clone());
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    /// An expression whose type does not match the type required by its context.
+    Mismatch {
+        expected: Type,
+        found: Type,
+        span: Span,
+        because: Option<Box<Because>>,
+    },
+    /// A type variable that would have to contain itself, e.g. the type of `x` in `{x => x(x)}`.
+    Recursive { var: Type, typ: Type, span: Span },
+    /// An identifier that is not bound.
+    FreeIdent { name: String, span: Span },
+    /// An application with a different number of arguments than the function has parameters.
+    Arity {
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// An expression of a type whose values cannot be compared with `==`, i.e. a function.
+    NotComparable { typ: Type, span: Span },
+}
+
+/// The expression that requires the expected type of a `TypeError::Mismatch`, e.g. the `+` expression of an operand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Because {
+    /// What the expression is, e.g. "`+` expression".
+    pub what: &'static str,
+    pub span: Span,
+}
+
+impl TypeError {
+    pub fn span(&self) -> Span {
+        match self {
+            TypeError::Mismatch { span, .. }
+            | TypeError::Recursive { span, .. }
+            | TypeError::FreeIdent { span, .. }
+            | TypeError::Arity { span, .. }
+            | TypeError::NotComparable { span, .. } => *span,
+        }
+    }
 
+    pub fn diagnostic(&self) -> Diagnostic {
+        let span = self.span();
+        // Types in one message share the names of their type variables.
+        let mut printer = TypePrinter::new();
+        let (message, label) = match self {
+            TypeError::Mismatch {
+                expected, found, ..
+            } => {
+                let (expected, found) = (printer.print(expected), printer.print(found));
+                (
+                    format!("Expected {}, found {}.", expected, found),
+                    format!("found {}", found),
+                )
+            }
+            TypeError::Recursive { var, typ, .. } => {
+                let (var, typ) = (printer.print(var), printer.print(typ));
+                (
+                    format!("Recursive type: {} occurs in {}.", var, typ),
+                    format!("{} would have to be {}", var, typ),
+                )
+            }
+            TypeError::FreeIdent { name, .. } => (
+                format!("Free identifier '{}'.", name),
+                "not bound".to_string(),
+            ),
+            TypeError::Arity {
+                expected, found, ..
+            } => (
+                format!("Expected {} arguments, found {}.", expected, found),
+                format!("applied to {} arguments", found),
+            ),
+            TypeError::NotComparable { typ, .. } => (
+                format!(
+                    "Values of type {} cannot be compared for equality.",
+                    printer.print(typ)
+                ),
+                "cannot be compared".to_string(),
+            ),
+        };
+
+        let mut diagnostic = Diagnostic::new("Type error", message, Label::new(span, label));
+        if let TypeError::Mismatch {
+            expected,
+            because: Some(because),
+            ..
+        } = self
+        {
+            diagnostic.secondary.push(Label::new(
+                because.span,
+                format!(
+                    "expected {} because of this {}",
+                    printer.print(expected),
+                    because.what
+                ),
+            ));
+        }
+        diagnostic
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.diagnostic())
+    }
+}
+
+// Why two types could not be unified.
+enum UnifyError {
+    Mismatch,
+    Recursive(Type, Type),
+    // A function type that an equality type variable would have to be.
+    NotComparable(Type),
+}
+
+pub fn type_check(expr: Expr, tenv: TEnv) -> Result<Type, TypeError> {
+    stack::grow(|| check(expr, tenv))
+}
+
+fn check(expr: Expr, mut tenv: TEnv) -> Result<Type, TypeError> {
+    // The expression, for the operands whose types it requires.
+    let span = expr.span;
+    let this = Some(Because {
+        what: describe(&expr.kind),
+        span,
+    });
+    match expr.into_kind() {
+        ExprKind::Num(_) => Ok(Type::Num),
+        ExprKind::Float(_) => Ok(Type::Float),
+        ExprKind::Bool(_) => Ok(Type::Bool),
+        ExprKind::Str(_) => Ok(Type::Str),
+        ExprKind::Add(left, right)
+        | ExprKind::Sub(left, right)
+        | ExprKind::Mul(left, right)
+        | ExprKind::Div(left, right)
+        | ExprKind::Mod(left, right)
+        | ExprKind::Pow(left, right) => {
+            let (left_span, right_span) = (left.span, right.span);
+            let lt = type_check(*left, tenv.clone())?;
+            let rt = type_check(*right, tenv.clone())?;
+            unify(lt, Type::Num, left_span, this.clone())?;
+            unify(rt, Type::Num, right_span, this)?;
+            Ok(Type::Num)
+        }
+        ExprKind::FAdd(left, right)
+        | ExprKind::FSub(left, right)
+        | ExprKind::FMul(left, right)
+        | ExprKind::FDiv(left, right) => {
+            let (left_span, right_span) = (left.span, right.span);
+            let lt = type_check(*left, tenv.clone())?;
+            let rt = type_check(*right, tenv.clone())?;
+            unify(lt, Type::Float, left_span, this.clone())?;
+            unify(rt, Type::Float, right_span, this)?;
+            Ok(Type::Float)
+        }
+        ExprKind::FLt(left, right)
+        | ExprKind::FLe(left, right)
+        | ExprKind::FGt(left, right)
+        | ExprKind::FGe(left, right) => {
+            let (left_span, right_span) = (left.span, right.span);
+            let lt = type_check(*left, tenv.clone())?;
+            let rt = type_check(*right, tenv.clone())?;
+            unify(lt, Type::Float, left_span, this.clone())?;
+            unify(rt, Type::Float, right_span, this)?;
+            Ok(Type::Bool)
+        }
+        ExprKind::Concat(left, right) => {
+            let (left_span, right_span) = (left.span, right.span);
+            let lt = type_check(*left, tenv.clone())?;
+            let rt = type_check(*right, tenv.clone())?;
+            unify(lt, Type::Str, left_span, this.clone())?;
+            unify(rt, Type::Str, right_span, this)?;
+            Ok(Type::Str)
+        }
+        ExprKind::Lt(left, right)
+        | ExprKind::Le(left, right)
+        | ExprKind::Gt(left, right)
+        | ExprKind::Ge(left, right) => {
+            let (left_span, right_span) = (left.span, right.span);
+            let lt = type_check(*left, tenv.clone())?;
+            let rt = type_check(*right, tenv.clone())?;
+            unify(lt, Type::Num, left_span, this.clone())?;
+            unify(rt, Type::Num, right_span, this)?;
+            Ok(Type::Bool)
+        }
+        // Any two values of the same type can be compared for equality, unless they are functions.
+        ExprKind::Eq(left, right) | ExprKind::Ne(left, right) => {
+            let (left_span, right_span) = (left.span, right.span);
+            let lt = type_check(*left, tenv.clone())?;
+            let rt = type_check(*right, tenv.clone())?;
+            unify(lt.clone(), fresh_eq_var(), left_span, None)?;
+            let because = Because {
+                what: "operand",
+                span: left_span,
+            };
+            unify(rt, lt, right_span, Some(because))?;
+            Ok(Type::Bool)
+        }
+        ExprKind::FNeg(operand) => {
+            let span = operand.span;
+            let ot = type_check(*operand, tenv)?;
+            unify(ot, Type::Float, span, this)?;
+            Ok(Type::Float)
+        }
+        ExprKind::Not(operand) => {
+            let span = operand.span;
+            let ot = type_check(*operand, tenv)?;
+            unify(ot, Type::Bool, span, this)?;
+            Ok(Type::Bool)
+        }
+        ExprKind::And(left, right) | ExprKind::Or(left, right) => {
+            let (left_span, right_span) = (left.span, right.span);
+            let lt = type_check(*left, tenv.clone())?;
+            let rt = type_check(*right, tenv.clone())?;
+            unify(lt, Type::Bool, left_span, this.clone())?;
+            unify(rt, Type::Bool, right_span, this)?;
+            Ok(Type::Bool)
+        }
+        ExprKind::If(cond, then, otherwise) => {
+            let (cond_span, then_span, otherwise_span) = (cond.span, then.span, otherwise.span);
+            let ct = type_check(*cond, tenv.clone())?;
+            unify(ct, Type::Bool, cond_span, this)?;
+            let tt = type_check(*then, tenv.clone())?;
+            let ot = type_check(*otherwise, tenv)?;
+            let because = Because {
+                what: "branch",
+                span: then_span,
+            };
+            unify(ot, tt.clone(), otherwise_span, Some(because))?;
+            Ok(tt)
+        }
+        ExprKind::Val(name, typ, expr, body) => {
+            let scheme = type_check_binding(&name, typ, *expr, false, &tenv)?;
             type_check(*body, {
-                tenv.push((name, typ));
+                tenv.push((name, scheme));
                 tenv
             })
         }
-        Expr::Id(ident) => tenv
-            .iter()
-            .find(|(x, _)| *x == ident)
-            .expect("free identifier error")
-            .1
-            .clone(),
-        Expr::Fun(name, typ, body) => Type::Fun(
-            Box::new(typ.clone()),
-            Box::new(type_check(*body, {
-                tenv.push((name, typ));
+        ExprKind::Rec(name, typ, expr, body) => {
+            let scheme = type_check_binding(&name, typ, *expr, true, &tenv)?;
+            type_check(*body, {
+                tenv.push((name, scheme));
                 tenv
-            })),
+            })
+        }
+        // The innermost binding shadows the others, and every binding shadows the builtins.
+        ExprKind::Id(ident) => match tenv.iter().rev().find(|(x, _)| *x == ident) {
+            Some((_, scheme)) => Ok(instantiate(scheme)),
+            None => match Builtin::lookup(&ident) {
+                Some(builtin) => Ok(builtin.typ()),
+                None => Err(TypeError::FreeIdent { name: ident, span }),
+            },
+        },
+        ExprKind::Fun(params, body) => {
+            let mut param_types = vec![];
+            for (name, typ) in params {
+                param_types.push(typ.clone());
+                tenv.push((name, Scheme::mono(typ)));
+            }
+            Ok(Type::Fun(param_types, Box::new(type_check(*body, tenv)?)))
+        }
+        ExprKind::Ascribe(expr, typ) => {
+            let span = expr.span;
+            let et = type_check(*expr, tenv)?;
+            unify(et, typ.clone(), span, None)?;
+            Ok(typ)
+        }
+        ExprKind::App(func, args) => {
+            let func_span = func.span;
+            let ft = type_check(*func, tenv.clone())?;
+            let mut arg_types = vec![];
+            for arg in args {
+                let arg_span = arg.span;
+                arg_types.push((type_check(arg, tenv.clone())?, arg_span));
+            }
+            match resolve(ft) {
+                // Check the arguments against the parameters, so that a mismatch points at an argument.
+                Type::Fun(pts, rt) => {
+                    if pts.len() != arg_types.len() {
+                        return Err(TypeError::Arity {
+                            expected: pts.len(),
+                            found: arg_types.len(),
+                            span,
+                        });
+                    }
+                    for ((at, arg_span), pt) in arg_types.into_iter().zip(pts) {
+                        let because = Because {
+                            what: "function",
+                            span: func_span,
+                        };
+                        unify(at, pt, arg_span, Some(because))?;
+                    }
+                    Ok(*rt)
+                }
+                ft => {
+                    let rt = fresh_var();
+                    let ats = arg_types.into_iter().map(|(at, _)| at).collect();
+                    unify(ft, Type::Fun(ats, Box::new(rt.clone())), func_span, None)?;
+                    Ok(rt)
+                }
+            }
+        }
+    }
+}
+
+/// Checks the definition `expr` of `name` annotated with `typ`, and returns the scheme `name` is bound to.
+///
+/// If `rec`, `name` is also bound in `expr`, where it is monomorphic.
+pub fn type_check_binding(
+    name: &str,
+    typ: Type,
+    expr: Expr,
+    rec: bool,
+    tenv: &TEnv,
+) -> Result<Scheme, TypeError> {
+    let span = expr.span;
+    let is_value = expr.is_value();
+    let mut expr_tenv = tenv.clone();
+    if rec {
+        expr_tenv.push((name.to_string(), Scheme::mono(typ.clone())));
+    }
+    let et = type_check(expr, expr_tenv)?;
+    unify(et, typ.clone(), span, None)?;
+
+    if is_value {
+        Ok(generalize(tenv, typ))
+    } else {
+        Ok(Scheme::mono(typ))
+    }
+}
+
+// Describes the expressions whose operands must have a certain type.
+fn describe(kind: &ExprKind) -> &'static str {
+    match kind {
+        ExprKind::Add(..) => "`+` expression",
+        ExprKind::Sub(..) => "`-` expression",
+        ExprKind::Mul(..) => "`*` expression",
+        ExprKind::Div(..) => "`/` expression",
+        ExprKind::Mod(..) => "`%` expression",
+        ExprKind::Pow(..) => "`**` expression",
+        ExprKind::FAdd(..) => "`+.` expression",
+        ExprKind::FSub(..) => "`-.` expression",
+        ExprKind::FMul(..) => "`*.` expression",
+        ExprKind::FDiv(..) => "`/.` expression",
+        ExprKind::FNeg(..) => "`-.` expression",
+        ExprKind::FLt(..) => "`<.` expression",
+        ExprKind::FLe(..) => "`<=.` expression",
+        ExprKind::FGt(..) => "`>.` expression",
+        ExprKind::FGe(..) => "`>=.` expression",
+        ExprKind::Concat(..) => "`++` expression",
+        ExprKind::Lt(..) => "`<` expression",
+        ExprKind::Le(..) => "`<=` expression",
+        ExprKind::Gt(..) => "`>` expression",
+        ExprKind::Ge(..) => "`>=` expression",
+        ExprKind::Not(..) => "`!` expression",
+        ExprKind::And(..) => "`and` expression",
+        ExprKind::Or(..) => "`or` expression",
+        ExprKind::If(..) => "`if` expression",
+        _ => "expression",
+    }
+}
+
+fn fresh_var() -> Type {
+    Type::Var {
+        typ: Rc::new(RefCell::new(None)),
+        eq: false,
+    }
+}
+
+fn fresh_eq_var() -> Type {
+    Type::Var {
+        typ: Rc::new(RefCell::new(None)),
+        eq: true,
+    }
+}
+
+// A fresh type variable that is an equality type variable if `var` is.
+fn fresh_like(var: &Type) -> Type {
+    match var {
+        Type::Var { eq: true, .. } => fresh_eq_var(),
+        _ => fresh_var(),
+    }
+}
+
+/// Quantifies the type variables of `typ` that are not bound in `tenv`.
+fn generalize(tenv: &TEnv, typ: Type) -> Scheme {
+    let mut env_vars = vec![];
+    for (_, scheme) in tenv {
+        free_vars(&scheme.typ, &scheme.vars, &mut env_vars);
+    }
+
+    let mut vars = vec![];
+    free_vars(&typ, &env_vars, &mut vars);
+    Scheme { vars, typ }
+}
+
+/// Replaces the quantified variables of `scheme` with fresh type variables.
+fn instantiate(scheme: &Scheme) -> Type {
+    let subst: Vec<(Type, Type)> = scheme
+        .vars
+        .iter()
+        .map(|var| (var.clone(), fresh_like(var)))
+        .collect();
+    substitute(scheme.typ.clone(), &subst)
+}
+
+/// Copies `tenv` with fresh type variables in place of its unbound ones,
+/// so that checking against the copy cannot change the types in `tenv`.
+pub fn fresh_copy(tenv: &TEnv) -> TEnv {
+    // One substitution for the whole environment keeps the variables shared by different bindings shared.
+    let mut subst = vec![];
+    tenv.iter()
+        .map(|(name, scheme)| {
+            let vars = scheme
+                .vars
+                .iter()
+                .map(|var| copy_type(var, &mut subst))
+                .collect();
+            let typ = copy_type(&scheme.typ, &mut subst);
+            (name.clone(), Scheme { vars, typ })
+        })
+        .collect()
+}
+
+// Copies `typ`, replacing its unbound type variables with the fresh ones they are mapped to in `subst`.
+fn copy_type(typ: &Type, subst: &mut Vec<(Type, Type)>) -> Type {
+    match resolve(typ.clone()) {
+        Type::Fun(ps, r) => Type::Fun(
+            ps.iter().map(|p| copy_type(p, subst)).collect(),
+            Box::new(copy_type(&r, subst)),
         ),
-        Expr::App(func, arg) => {
-            let ft = type_check(*func, tenv.clone());
-            let at = type_check(*arg, tenv);
-            let rt = Type::Var {
-                typ: Rc::new(RefCell::new(None)),
-            };
-            unify(ft, Type::Fun(Box::new(at), Box::new(rt.clone())));
-            rt
+        var @ Type::Var { .. } => match subst.iter().find(|(x, _)| *x == var) {
+            Some((_, fresh)) => fresh.clone(),
+            None => {
+                let fresh = fresh_like(&var);
+                subst.push((var, fresh.clone()));
+                fresh
+            }
+        },
+        typ => typ,
+    }
+}
+
+fn substitute(typ: Type, subst: &[(Type, Type)]) -> Type {
+    match resolve(typ) {
+        Type::Num => Type::Num,
+        Type::Float => Type::Float,
+        Type::Bool => Type::Bool,
+        Type::Str => Type::Str,
+        Type::Fun(ps, r) => Type::Fun(
+            ps.into_iter().map(|p| substitute(p, subst)).collect(),
+            Box::new(substitute(*r, subst)),
+        ),
+        var @ Type::Var { .. } => match subst.iter().find(|(x, _)| *x == var) {
+            Some((_, typ)) => typ.clone(),
+            None => var,
+        },
+    }
+}
+
+// Collects the unbound type variables of `typ` that are not in `bound` into `vars`.
+fn free_vars(typ: &Type, bound: &[Type], vars: &mut Vec<Type>) {
+    match resolve(typ.clone()) {
+        Type::Num | Type::Float | Type::Bool | Type::Str => (),
+        Type::Fun(ps, r) => {
+            for p in &ps {
+                free_vars(p, bound, vars);
+            }
+            free_vars(&r, bound, vars);
+        }
+        var @ Type::Var { .. } => {
+            if !bound.contains(&var) && !vars.contains(&var) {
+                vars.push(var);
+            }
         }
     }
 }
 
-fn error(s: String) {
-    // println!("elifihwlefnwe");
-    panic!("a type error occured: {}", s);
+/// Unifies the type `found` of the expression at `span` with the type `expected` of its context.
+fn unify(
+    found: Type,
+    expected: Type,
+    span: Span,
+    because: Option<Because>,
+) -> Result<(), TypeError> {
+    unify_types(found.clone(), expected.clone()).map_err(|error| match error {
+        UnifyError::Mismatch => TypeError::Mismatch {
+            expected,
+            found,
+            span,
+            because: because.map(Box::new),
+        },
+        UnifyError::Recursive(var, typ) => TypeError::Recursive { var, typ, span },
+        UnifyError::NotComparable(typ) => TypeError::NotComparable { typ, span },
+    })
 }
 
-fn unify(t1: Type, t2: Type) {
+fn unify_types(t1: Type, t2: Type) -> Result<(), UnifyError> {
+    stack::grow(|| unify_resolved(t1, t2))
+}
+
+fn unify_resolved(t1: Type, t2: Type) -> Result<(), UnifyError> {
     match (resolve(t1), resolve(t2)) {
-        (ref t1 @ Type::Var { ref typ }, t2) => {
+        (ref t1 @ Type::Var { ref typ, eq }, t2) => {
             if t1 == &t2 {
                 // NOP
             } else if occurs(t1, t2.clone()) {
-                error(format!("recursive type: {}, {}", t1, t2))
+                return Err(UnifyError::Recursive(t1.clone(), t2));
             } else {
+                if eq {
+                    admit_equality(t2.clone())?;
+                }
                 let mut t = typ.borrow_mut();
                 *t = Some(t2);
             }
+            Ok(())
         }
-        (t1, t2 @ Type::Var { typ: _ }) => unify(t2, t1),
-        (Type::Num, Type::Num) => {
+        (t1, t2 @ Type::Var { .. }) => unify_types(t2, t1),
+        (Type::Num, Type::Num)
+        | (Type::Float, Type::Float)
+        | (Type::Bool, Type::Bool)
+        | (Type::Str, Type::Str) => {
             // NOP
+            Ok(())
         }
-        (Type::Fun(t3, t4), Type::Fun(t5, t6)) => {
-            unify(*t3, *t5);
-            unify(*t4, *t6);
+        (Type::Fun(ps1, r1), Type::Fun(ps2, r2)) if ps1.len() == ps2.len() => {
+            for (p1, p2) in ps1.into_iter().zip(ps2) {
+                unify_types(p1, p2)?;
+            }
+            unify_types(*r1, *r2)
         }
-        (Type::Num, Type::Fun(_, _)) | (Type::Fun(_, _), Type::Num) => {
-            error(format!("incompatible types: Num and Function"))
+        _ => Err(UnifyError::Mismatch),
+    }
+}
+
+// Restricts `typ` to the types whose values can be compared with `==`.
+fn admit_equality(typ: Type) -> Result<(), UnifyError> {
+    match resolve(typ) {
+        Type::Num | Type::Float | Type::Bool | Type::Str | Type::Var { eq: true, .. } => Ok(()),
+        fun @ Type::Fun(..) => Err(UnifyError::NotComparable(fun)),
+        // The variable can only be bound to the types an equality type variable can.
+        Type::Var { typ, eq: false } => {
+            *typ.borrow_mut() = Some(fresh_eq_var());
+            Ok(())
         }
     }
 }
 
 pub fn resolve(typ: Type) -> Type {
-    match typ {
-        Type::Var { typ: t } => match t.borrow().as_ref() {
+    match &typ {
+        Type::Var { typ: t, .. } => match t.borrow().as_ref() {
             Some(inner) => resolve(inner.clone()),
-            None => Type::Var { typ: Rc::clone(&t) },
+            None => typ.clone(),
         },
         _ => typ,
     }
@@ -93,11 +569,11 @@ pub fn resolve(typ: Type) -> Type {
 // checks if t1 occurs in t2
 fn occurs(t1: &Type, t2: Type) -> bool {
     match resolve(t2) {
-        Type::Num => false,
-        Type::Fun(l, r) => occurs(t1, *l) || occurs(t1, *r),
-        Type::Var { typ: typ2 } => {
-            if let Type::Var { typ: typ1 } = t1 {
-                Rc::ptr_eq(&typ1, &typ2)
+        Type::Num | Type::Float | Type::Bool | Type::Str => false,
+        Type::Fun(ps, r) => ps.into_iter().any(|p| occurs(t1, p)) || occurs(t1, *r),
+        Type::Var { typ: typ2, .. } => {
+            if let Type::Var { typ: typ1, .. } = t1 {
+                Rc::ptr_eq(typ1, &typ2)
             } else {
                 false
             }
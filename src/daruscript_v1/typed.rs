@@ -1,21 +1,34 @@
-use super::{type_checker::resolve, untyped::Expr as Untyped};
+use super::span::Span;
+use super::stack;
+use super::type_checker::resolve;
+use super::untyped::{Expr as Untyped, ExprKind as UntypedKind};
 use core::fmt;
 use std::{cell::RefCell, rc::Rc};
 
 #[derive(Debug, Clone)]
 pub enum Type {
     Num,
-    Fun(Box<Type>, Box<Type>),
-    // Type Variable
-    Var { typ: Rc<RefCell<Option<Type>>> },
+    Float,
+    Bool,
+    Str,
+    // Parameter types and return type
+    Fun(Vec<Type>, Box<Type>),
+    // Type Variable, which only stands for types whose values can be compared with `==` if `eq`
+    Var {
+        typ: Rc<RefCell<Option<Type>>>,
+        eq: bool,
+    },
 }
 
 impl PartialEq for Type {
     fn eq(&self, other: &Type) -> bool {
         match (self, other) {
-            (Type::Num, Type::Num) => true,
+            (Type::Num, Type::Num)
+            | (Type::Float, Type::Float)
+            | (Type::Bool, Type::Bool)
+            | (Type::Str, Type::Str) => true,
             (Type::Fun(x1, r1), Type::Fun(x2, r2)) => x1 == x2 && r1 == r2,
-            (Type::Var { typ: typ1 }, Type::Var { typ: typ2 }) => Rc::ptr_eq(typ1, typ2),
+            (Type::Var { typ: typ1, .. }, Type::Var { typ: typ2, .. }) => Rc::ptr_eq(typ1, typ2),
             _ => false,
         }
     }
@@ -23,118 +36,383 @@ impl PartialEq for Type {
 
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match resolve(self.clone()) {
-            Type::Num => write!(f, "Num"),
-            Type::Fun(l, r) => write!(f, "{} -> {}", l, r),
-            Type::Var { typ } => write!(
-                f,
-                "Var({})",
-                match typ.borrow().clone() {
-                    Some(x) => x.to_string(),
-                    None => "None".to_string(),
+        f.write_str(&TypePrinter::new().print(self))
+    }
+}
+
+/// Prints types, naming unbound type variables `'a`, `'b`, ... in the order they are first printed.
+///
+/// Every type printed by the same printer shares the names,
+/// so that the types in a single message can be compared with each other.
+#[derive(Default)]
+pub struct TypePrinter {
+    vars: Vec<Rc<RefCell<Option<Type>>>>,
+}
+
+impl TypePrinter {
+    pub fn new() -> TypePrinter {
+        TypePrinter::default()
+    }
+
+    pub fn print(&mut self, typ: &Type) -> String {
+        match resolve(typ.clone()) {
+            Type::Num => "Num".to_string(),
+            Type::Float => "Float".to_string(),
+            Type::Bool => "Bool".to_string(),
+            Type::Str => "Str".to_string(),
+            Type::Fun(params, ret) => {
+                let params = match params.as_slice() {
+                    // `->` is right-associative, so a function parameter needs parentheses.
+                    [param] => match resolve(param.clone()) {
+                        Type::Fun(..) => format!("({})", self.print(param)),
+                        _ => self.print(param),
+                    },
+                    params => {
+                        let params: Vec<String> = params.iter().map(|p| self.print(p)).collect();
+                        format!("({})", params.join(", "))
+                    }
+                };
+                format!("{} -> {}", params, self.print(&ret))
+            }
+            Type::Var { typ, eq } => {
+                let index = match self.vars.iter().position(|var| Rc::ptr_eq(var, &typ)) {
+                    Some(index) => index,
+                    None => {
+                        self.vars.push(typ);
+                        self.vars.len() - 1
+                    }
+                };
+                // Equality type variables are written `''a`, as in Standard ML.
+                let name = var_name(index);
+                if eq {
+                    format!("'{}", name)
+                } else {
+                    name
                 }
-            ),
+            }
         }
     }
 }
 
+// 'a, ..., 'z, 'a1, ..., 'z1, 'a2, ...
+fn var_name(index: usize) -> String {
+    let letter = (b'a' + (index % 26) as u8) as char;
+    match index / 26 {
+        0 => format!("'{}", letter),
+        n => format!("'{}{}", letter, n),
+    }
+}
+
+#[derive(Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+// Spans are ignored, so that trees parsed from different sources can be compared.
+impl PartialEq for Expr {
+    fn eq(&self, other: &Expr) -> bool {
+        stack::grow(|| self.kind == other.kind)
+    }
+}
+
+// A long chain of operators nests the tree deeper than the derived implementations can recurse,
+// so cloning and dropping grow the stack like the passes over the tree.
+impl Clone for Expr {
+    fn clone(&self) -> Expr {
+        stack::grow(|| Expr::new(self.kind.clone(), self.span))
+    }
+}
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let kind = std::mem::replace(&mut self.kind, ExprKind::Num(0));
+        stack::grow(|| drop(kind));
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
-pub enum Expr {
+pub enum ExprKind {
     Num(isize),
+    Float(f64),
+    Bool(bool),
+    Str(String),
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
+    // Remainder `l % r`, with the sign of `l`
+    Mod(Box<Expr>, Box<Expr>),
+    // Exponentiation `l ** r`
+    Pow(Box<Expr>, Box<Expr>),
+    // Floating-point arithmetic and comparisons, e.g. `l +. r` and `l <. r`
+    FAdd(Box<Expr>, Box<Expr>),
+    FSub(Box<Expr>, Box<Expr>),
+    FMul(Box<Expr>, Box<Expr>),
+    FDiv(Box<Expr>, Box<Expr>),
+    // Floating-point negation `-.x`
+    FNeg(Box<Expr>),
+    FLt(Box<Expr>, Box<Expr>),
+    FLe(Box<Expr>, Box<Expr>),
+    FGt(Box<Expr>, Box<Expr>),
+    FGe(Box<Expr>, Box<Expr>),
+    // String concatenation `l ++ r`
+    Concat(Box<Expr>, Box<Expr>),
+    // Comparisons
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+    Lt(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Gt(Box<Expr>, Box<Expr>),
+    Ge(Box<Expr>, Box<Expr>),
+    // Logical operators, where `And` and `Or` short-circuit
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    // `if (cond) { then } else { otherwise }`
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
     Val(String, Type, Box<Expr>, Box<Expr>),
+    // `{def f = {n => ...}; body}`, where `f` is also bound in its own definition
+    Rec(String, Type, Box<Expr>, Box<Expr>),
     Id(String),
-    // First-class functions
-    Fun(String, Type, Box<Expr>),
-    // Apply first-class function
-    App(Box<Expr>, Box<Expr>),
+    // First-class functions `{x, y => body}`
+    Fun(Vec<(String, Type)>, Box<Expr>),
+    // Apply first-class function to its arguments
+    App(Box<Expr>, Vec<Expr>),
+    // `(expr : Type)`
+    Ascribe(Box<Expr>, Type),
 }
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Expr::Num(n) => write!(f, "Num({})", n),
-            Expr::Add(left, right) => write!(f, "Add({}, {})", left.to_string(), right.to_string()),
-            Expr::Sub(left, right) => write!(f, "Sub({}, {})", left.to_string(), right.to_string()),
-            Expr::Val(ident, _typ, expr, body) => write!(
-                f,
-                "Val(\"{}\", {}, {})",
-                ident.to_string(),
-                expr.to_string(),
-                body.to_string()
-            ),
-            Expr::Id(ident) => write!(f, "Id(\"{}\")", ident.to_string()),
-            Expr::Fun(param, _typ, body) => {
-                write!(f, "Fun(\"{}\", {})", param.to_string(), body.to_string())
+        stack::grow(|| self.fmt_kind(f))
+    }
+}
+
+impl Expr {
+    fn fmt_kind(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Num(n) => write!(f, "Num({})", n),
+            ExprKind::Float(x) => write!(f, "Float({:?})", x),
+            ExprKind::Bool(b) => write!(f, "Bool({})", b),
+            ExprKind::Str(s) => write!(f, "Str({:?})", s),
+            ExprKind::Add(left, right) => write!(f, "Add({}, {})", left, right),
+            ExprKind::Sub(left, right) => write!(f, "Sub({}, {})", left, right),
+            ExprKind::Val(ident, _typ, expr, body) => {
+                write!(f, "Val(\"{}\", {}, {})", ident, expr, body)
+            }
+            ExprKind::Rec(ident, _typ, expr, body) => {
+                write!(f, "Rec(\"{}\", {}, {})", ident, expr, body)
+            }
+            ExprKind::Id(ident) => write!(f, "Id(\"{}\")", ident),
+            ExprKind::Fun(params, body) => {
+                write!(f, "Fun(")?;
+                for (param, _typ) in params {
+                    write!(f, "\"{}\", ", param)?;
+                }
+                write!(f, "{})", body)
+            }
+            ExprKind::App(func, args) => {
+                write!(f, "App({}", func)?;
+                for arg in args {
+                    write!(f, ", {}", arg)?;
+                }
+                write!(f, ")")
             }
-            Expr::App(func, arg) => write!(f, "App({}, {})", func.to_string(), arg.to_string()),
-            Expr::Mul(left, right) => write!(f, "Mul({}, {})", left.to_string(), right.to_string()),
-            Expr::Div(left, right) => write!(f, "Div({}, {})", left.to_string(), right.to_string()),
+            ExprKind::Mul(left, right) => write!(f, "Mul({}, {})", left, right),
+            ExprKind::Div(left, right) => write!(f, "Div({}, {})", left, right),
+            ExprKind::Mod(left, right) => write!(f, "Mod({}, {})", left, right),
+            ExprKind::Pow(left, right) => write!(f, "Pow({}, {})", left, right),
+            ExprKind::FAdd(left, right) => write!(f, "FAdd({}, {})", left, right),
+            ExprKind::FSub(left, right) => write!(f, "FSub({}, {})", left, right),
+            ExprKind::FMul(left, right) => write!(f, "FMul({}, {})", left, right),
+            ExprKind::FDiv(left, right) => write!(f, "FDiv({}, {})", left, right),
+            ExprKind::FNeg(expr) => write!(f, "FNeg({})", expr),
+            ExprKind::FLt(left, right) => write!(f, "FLt({}, {})", left, right),
+            ExprKind::FLe(left, right) => write!(f, "FLe({}, {})", left, right),
+            ExprKind::FGt(left, right) => write!(f, "FGt({}, {})", left, right),
+            ExprKind::FGe(left, right) => write!(f, "FGe({}, {})", left, right),
+            ExprKind::Concat(left, right) => write!(f, "Concat({}, {})", left, right),
+            ExprKind::Eq(left, right) => write!(f, "Eq({}, {})", left, right),
+            ExprKind::Ne(left, right) => write!(f, "Ne({}, {})", left, right),
+            ExprKind::Lt(left, right) => write!(f, "Lt({}, {})", left, right),
+            ExprKind::Le(left, right) => write!(f, "Le({}, {})", left, right),
+            ExprKind::Gt(left, right) => write!(f, "Gt({}, {})", left, right),
+            ExprKind::Ge(left, right) => write!(f, "Ge({}, {})", left, right),
+            ExprKind::Not(expr) => write!(f, "Not({})", expr),
+            ExprKind::And(left, right) => write!(f, "And({}, {})", left, right),
+            ExprKind::Or(left, right) => write!(f, "Or({}, {})", left, right),
+            ExprKind::If(cond, then, otherwise) => {
+                write!(f, "If({}, {}, {})", cond, then, otherwise)
+            }
+            ExprKind::Ascribe(expr, typ) => write!(f, "Ascribe({}, {})", expr, typ),
         }
     }
 }
 
 impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Expr {
+        Expr { kind, span }
+    }
+
+    /// Moves the kind out of the expression, which `Drop` does not allow otherwise.
+    pub fn into_kind(mut self) -> ExprKind {
+        std::mem::replace(&mut self.kind, ExprKind::Num(0))
+    }
+
+    /// Whether the expression is a syntactic value, which can be evaluated without any effect.
+    ///
+    /// Only values are generalized by `val`, since generalizing any other expression would be unsound
+    /// once the language has effects (the value restriction).
+    pub fn is_value(&self) -> bool {
+        matches!(
+            self.kind,
+            ExprKind::Num(_)
+                | ExprKind::Float(_)
+                | ExprKind::Bool(_)
+                | ExprKind::Str(_)
+                | ExprKind::Id(_)
+                | ExprKind::Fun(..)
+        )
+    }
+
     pub fn type_erase(&self) -> Untyped {
         Expr::erase(self)
     }
 
     fn erase(expr: &Expr) -> Untyped {
-        match expr {
-            Expr::Num(n) => Untyped::Num(*n),
-            Expr::Add(l, r) => Untyped::Add(Box::new(Expr::erase(l)), Box::new(Expr::erase(r))),
-            Expr::Sub(l, r) => Untyped::Sub(Box::new(Expr::erase(l)), Box::new(Expr::erase(r))),
-            Expr::Mul(l, r) => Untyped::Mul(Box::new(Expr::erase(l)), Box::new(Expr::erase(r))),
-            Expr::Div(l, r) => Untyped::Div(Box::new(Expr::erase(l)), Box::new(Expr::erase(r))),
-            Expr::Val(x, _, e, b) => Untyped::Val(
-                x.to_string(),
-                Box::new(Expr::erase(e)),
-                Box::new(Expr::erase(b)),
-            ),
-            Expr::Id(x) => Untyped::Id(x.to_string()),
-            Expr::Fun(p, _, b) => Untyped::Fun(p.to_string(), Box::new(Expr::erase(b))),
-            Expr::App(f, a) => Untyped::App(Box::new(Expr::erase(f)), Box::new(Expr::erase(a))),
+        stack::grow(|| Expr::erase_kind(expr))
+    }
+
+    fn erase_kind(expr: &Expr) -> Untyped {
+        let erase = |expr: &Expr| Box::new(Expr::erase(expr));
+        let kind = match &expr.kind {
+            ExprKind::Num(n) => UntypedKind::Num(*n),
+            ExprKind::Float(x) => UntypedKind::Float(*x),
+            ExprKind::Bool(b) => UntypedKind::Bool(*b),
+            ExprKind::Str(s) => UntypedKind::Str(s.to_string()),
+            ExprKind::Add(l, r) => UntypedKind::Add(erase(l), erase(r)),
+            ExprKind::Sub(l, r) => UntypedKind::Sub(erase(l), erase(r)),
+            ExprKind::Mul(l, r) => UntypedKind::Mul(erase(l), erase(r)),
+            ExprKind::Div(l, r) => UntypedKind::Div(erase(l), erase(r)),
+            ExprKind::Mod(l, r) => UntypedKind::Mod(erase(l), erase(r)),
+            ExprKind::Pow(l, r) => UntypedKind::Pow(erase(l), erase(r)),
+            ExprKind::FAdd(l, r) => UntypedKind::FAdd(erase(l), erase(r)),
+            ExprKind::FSub(l, r) => UntypedKind::FSub(erase(l), erase(r)),
+            ExprKind::FMul(l, r) => UntypedKind::FMul(erase(l), erase(r)),
+            ExprKind::FDiv(l, r) => UntypedKind::FDiv(erase(l), erase(r)),
+            ExprKind::FNeg(e) => UntypedKind::FNeg(erase(e)),
+            ExprKind::FLt(l, r) => UntypedKind::FLt(erase(l), erase(r)),
+            ExprKind::FLe(l, r) => UntypedKind::FLe(erase(l), erase(r)),
+            ExprKind::FGt(l, r) => UntypedKind::FGt(erase(l), erase(r)),
+            ExprKind::FGe(l, r) => UntypedKind::FGe(erase(l), erase(r)),
+            ExprKind::Concat(l, r) => UntypedKind::Concat(erase(l), erase(r)),
+            ExprKind::Eq(l, r) => UntypedKind::Eq(erase(l), erase(r)),
+            ExprKind::Ne(l, r) => UntypedKind::Ne(erase(l), erase(r)),
+            ExprKind::Lt(l, r) => UntypedKind::Lt(erase(l), erase(r)),
+            ExprKind::Le(l, r) => UntypedKind::Le(erase(l), erase(r)),
+            ExprKind::Gt(l, r) => UntypedKind::Gt(erase(l), erase(r)),
+            ExprKind::Ge(l, r) => UntypedKind::Ge(erase(l), erase(r)),
+            ExprKind::Not(e) => UntypedKind::Not(erase(e)),
+            ExprKind::And(l, r) => UntypedKind::And(erase(l), erase(r)),
+            ExprKind::Or(l, r) => UntypedKind::Or(erase(l), erase(r)),
+            ExprKind::If(c, t, o) => UntypedKind::If(erase(c), erase(t), erase(o)),
+            ExprKind::Val(x, _, e, b) => UntypedKind::Val(x.to_string(), erase(e), erase(b)),
+            ExprKind::Rec(x, _, e, b) => UntypedKind::Rec(x.to_string(), erase(e), erase(b)),
+            ExprKind::Id(x) => UntypedKind::Id(x.to_string()),
+            ExprKind::Fun(ps, b) => {
+                UntypedKind::Fun(ps.iter().map(|(p, _)| p.to_string()).collect(), erase(b))
+            }
+            ExprKind::App(f, args) => {
+                UntypedKind::App(erase(f), args.iter().map(Expr::erase).collect())
+            }
+            // Only the type checker needs the annotation.
+            ExprKind::Ascribe(e, _) => Expr::erase(e).into_kind(),
+        };
+        Untyped::new(kind, expr.span)
+    }
+}
+
+/// A top-level input of the REPL or of a script.
+pub enum Input {
+    /// A top-level `val x = 100` or `def f = {n => ...}` without a body,
+    /// which stays bound for the following inputs.
+    Decl {
+        rec: bool,
+        name: String,
+        typ: Type,
+        expr: Expr,
+    },
+    Expr(Expr),
+}
+
+// A declaration is shown like a `Val` or `Rec` expression without the body.
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Decl {
+                rec, name, expr, ..
+            } => {
+                let binding = if *rec { "Rec" } else { "Val" };
+                write!(f, "{}(\"{}\", {})", binding, name, expr)
+            }
+            Input::Expr(expr) => write!(f, "{}", expr),
         }
     }
 }
 
-pub type TEnv = Vec<(String, Type)>;
+/// A type scheme `forall vars. typ`.
+///
+/// The unbound type variables listed in `vars` are replaced with fresh ones every time the scheme is used,
+/// which lets a `val`-bound function be used at different types.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheme {
+    pub vars: Vec<Type>,
+    pub typ: Type,
+}
+
+impl Scheme {
+    /// A scheme without quantified variables, e.g. for a function parameter.
+    pub fn mono(typ: Type) -> Scheme {
+        Scheme { vars: vec![], typ }
+    }
+}
+
+pub type TEnv = Vec<(String, Scheme)>;
 
 #[cfg(test)]
 mod tests {
     use std::cell::RefCell;
     use std::rc::Rc;
 
-    use super::Expr::*;
-    use super::Type;
-    use crate::daruscript_v1::type_checker::type_check;
+    use super::ExprKind::*;
+    use super::{Expr, ExprKind, Type, TypePrinter};
+    use crate::daruscript_v1::span::Span;
+    use crate::daruscript_v1::type_checker::{type_check, Because, TypeError};
+
+    fn e(kind: ExprKind) -> Box<Expr> {
+        Box::new(Expr::new(kind, Span::default()))
+    }
 
     #[test]
     fn addition_type_check() {
         assert_eq!(
-            type_check(
-                Add(
-                    Box::new(Add(Box::new(Num(1)), Box::new(Num(2)))),
-                    Box::new(Num(3))
-                ),
-                vec![]
-            )
-            .to_string(),
+            type_check(*e(Add(e(Add(e(Num(1)), e(Num(2)))), e(Num(3)))), vec![])
+                .unwrap()
+                .to_string(),
             "Num".to_string()
         );
         assert_eq!(
             type_check(
-                Val(
+                *e(Val(
                     String::from("x"),
                     Type::Num,
-                    Box::new(Num(6)),
-                    Box::new(Add(Box::new(Num(1)), Box::new(Id(String::from("x")))))
-                ),
+                    e(Num(6)),
+                    e(Add(e(Num(1)), e(Id(String::from("x")))))
+                )),
                 vec![]
             )
+            .unwrap()
             .to_string(),
             "Num".to_string()
         );
@@ -145,43 +423,54 @@ mod tests {
         // {x => y => x}(1)
         assert_eq!(
             type_check(
-                App(
-                    Box::new(Fun(
-                        "x".to_string(),
-                        Type::Var {
-                            typ: Rc::new(RefCell::new(None))
-                        },
-                        Box::new(Fun(
-                            "y".to_string(),
+                *e(App(
+                    e(Fun(
+                        vec![(
+                            "x".to_string(),
                             Type::Var {
-                                typ: Rc::new(RefCell::new(None))
-                            },
-                            Box::new(Id("x".to_string()))
-                        )),
+                                typ: Rc::new(RefCell::new(None)),
+                                eq: false,
+                            }
+                        )],
+                        e(Fun(
+                            vec![(
+                                "y".to_string(),
+                                Type::Var {
+                                    typ: Rc::new(RefCell::new(None)),
+                                    eq: false,
+                                }
+                            )],
+                            e(Id("x".to_string()))
+                        ))
                     )),
-                    Box::new(Num(1))
-                ),
+                    vec![*e(Num(1))]
+                )),
                 vec![]
             )
+            .unwrap()
             .to_string(),
-            "Var(None) -> Num".to_string()
+            "'a -> Num".to_string()
         );
 
         assert_eq!(
             // {x => x + 1}(2)
             type_check(
-                App(
-                    Box::new(Fun(
-                        "x".to_string(),
-                        Type::Var {
-                            typ: Rc::new(RefCell::new(None))
-                        },
-                        Box::new(Add(Box::new(Id("x".to_string())), Box::new(Num(1))))
+                *e(App(
+                    e(Fun(
+                        vec![(
+                            "x".to_string(),
+                            Type::Var {
+                                typ: Rc::new(RefCell::new(None)),
+                                eq: false,
+                            }
+                        )],
+                        e(Add(e(Id("x".to_string())), e(Num(1))))
                     )),
-                    Box::new(Num(2)),
-                ),
+                    vec![*e(Num(2))],
+                )),
                 vec![]
             )
+            .unwrap()
             .to_string(),
             "Num".to_string()
         );
@@ -189,45 +478,53 @@ mod tests {
         assert_eq!(
             // val x = 1; val x = x + 1; x
             type_check(
-                Val(
+                *e(Val(
                     "x".to_string(),
                     Type::Var {
-                        typ: Rc::new(RefCell::new(None))
+                        typ: Rc::new(RefCell::new(None)),
+                        eq: false,
                     },
-                    Box::new(Num(1)),
-                    Box::new(Val(
+                    e(Num(1)),
+                    e(Val(
                         "x".to_string(),
                         Type::Var {
-                            typ: Rc::new(RefCell::new(None))
+                            typ: Rc::new(RefCell::new(None)),
+                            eq: false,
                         },
-                        Box::new(Add(Box::new(Id("x".to_string())), Box::new(Num(1)))),
-                        Box::new(Id("x".to_string()))
+                        e(Add(e(Id("x".to_string())), e(Num(1)))),
+                        e(Id("x".to_string()))
                     )),
-                ),
+                )),
                 vec![]
             )
+            .unwrap()
             .to_string(),
             "Num".to_string()
         );
 
         assert_eq!(
             type_check(
-                Val(
+                *e(Val(
                     "f".to_string(),
                     Type::Var {
-                        typ: Rc::new(RefCell::new(None))
+                        typ: Rc::new(RefCell::new(None)),
+                        eq: false,
                     },
-                    Box::new(Fun(
-                        "x".to_string(),
-                        Type::Var {
-                            typ: Rc::new(RefCell::new(None))
-                        },
-                        Box::new(Add(Box::new(Id("x".to_string())), Box::new(Num(1))))
+                    e(Fun(
+                        vec![(
+                            "x".to_string(),
+                            Type::Var {
+                                typ: Rc::new(RefCell::new(None)),
+                                eq: false,
+                            }
+                        )],
+                        e(Add(e(Id("x".to_string())), e(Num(1))))
                     )),
-                    Box::new(App(Box::new(Id("f".to_string())), Box::new(Num(3))))
-                ),
+                    e(App(e(Id("f".to_string())), vec![*e(Num(3))]))
+                )),
                 vec![]
             )
+            .unwrap()
             .to_string(),
             "Num".to_string()
         );
@@ -235,124 +532,197 @@ mod tests {
         assert_eq!(
             // x = 6; 1 + x
             type_check(
-                Val(
+                *e(Val(
                     String::from("x"),
                     Type::Var {
-                        typ: Rc::new(RefCell::new(None))
+                        typ: Rc::new(RefCell::new(None)),
+                        eq: false,
                     },
-                    Box::new(Num(6)),
-                    Box::new(Add(Box::new(Num(1)), Box::new(Id(String::from("x")))))
-                ),
+                    e(Num(6)),
+                    e(Add(e(Num(1)), e(Id(String::from("x")))))
+                )),
                 vec![]
             )
+            .unwrap()
             .to_string(),
             "Num".to_string()
         );
 
         assert_eq!(
             // val x1 = x => x; val x2 = x1; val x3= x2(1); x1
+            // x1 is polymorphic, so applying x2 to 1 does not make it Num -> Num.
             type_check(
-                Val(
+                *e(Val(
                     "x1".to_string(),
                     Type::Var {
-                        typ: Rc::new(RefCell::new(None))
+                        typ: Rc::new(RefCell::new(None)),
+                        eq: false,
                     },
                     // expr
-                    Box::new(Fun(
-                        "x".to_string(),
-                        Type::Var {
-                            typ: Rc::new(RefCell::new(None))
-                        },
-                        Box::new(Id("x".to_string()))
+                    e(Fun(
+                        vec![(
+                            "x".to_string(),
+                            Type::Var {
+                                typ: Rc::new(RefCell::new(None)),
+                                eq: false,
+                            }
+                        )],
+                        e(Id("x".to_string()))
                     )),
                     // body
-                    Box::new(Val(
+                    e(Val(
                         "x2".to_string(),
                         Type::Var {
-                            typ: Rc::new(RefCell::new(None))
+                            typ: Rc::new(RefCell::new(None)),
+                            eq: false,
                         },
-                        Box::new(Id("x1".to_string())),
-                        Box::new(Val(
+                        e(Id("x1".to_string())),
+                        e(Val(
                             "x3".to_string(),
                             Type::Var {
-                                typ: Rc::new(RefCell::new(None))
+                                typ: Rc::new(RefCell::new(None)),
+                                eq: false,
                             },
-                            Box::new(App(Box::new(Id("x2".to_string())), Box::new(Num(1)))),
-                            Box::new(Id("x1".to_string()))
+                            e(App(e(Id("x2".to_string())), vec![*e(Num(1))])),
+                            e(Id("x1".to_string()))
                         ))
                     ))
-                ),
+                )),
                 vec![]
             )
+            .unwrap()
+            .to_string(),
+            "'a -> 'a".to_string()
+        );
+    }
+
+    #[test]
+    fn type_printing() {
+        let a = Type::Var {
+            typ: Rc::new(RefCell::new(None)),
+            eq: false,
+        };
+        let b = Type::Var {
+            typ: Rc::new(RefCell::new(None)),
+            eq: false,
+        };
+        let fun = |params: Vec<Type>, ret: Type| Type::Fun(params, Box::new(ret));
+
+        assert_eq!(fun(vec![a.clone()], a.clone()).to_string(), "'a -> 'a");
+        assert_eq!(
+            fun(
+                vec![fun(vec![b.clone()], a.clone())],
+                fun(vec![b.clone()], Type::Num)
+            )
             .to_string(),
-            "Num -> Num".to_string()
+            "('a -> 'b) -> 'a -> Num"
         );
+        assert_eq!(
+            fun(vec![a.clone(), fun(vec![Type::Bool], b.clone())], b.clone()).to_string(),
+            "('a, Bool -> 'b) -> 'b"
+        );
+
+        // Variables bound to a type are printed as that type.
+        let bound = Type::Var {
+            typ: Rc::new(RefCell::new(Some(fun(vec![Type::Num], Type::Num)))),
+            eq: false,
+        };
+        assert_eq!(
+            fun(vec![bound], a.clone()).to_string(),
+            "(Num -> Num) -> 'a"
+        );
+
+        // Names are shared by the types printed with the same printer.
+        let mut printer = TypePrinter::new();
+        assert_eq!(printer.print(&b), "'a");
+        assert_eq!(printer.print(&fun(vec![a], b)), "'b -> 'a");
     }
 
     #[test]
-    #[should_panic]
     fn type_inferred_invalid() {
-        type_check(
-            // x => x(x)
-            Fun(
-                "x".to_string(),
-                Type::Var {
-                    typ: Rc::new(RefCell::new(None)),
-                },
-                Box::new(App(
-                    Box::new(Id("x".to_string())),
-                    Box::new(Id("x".to_string())),
+        // x => x(x)
+        assert!(matches!(
+            type_check(
+                *e(Fun(
+                    vec![(
+                        "x".to_string(),
+                        Type::Var {
+                            typ: Rc::new(RefCell::new(None)),
+                            eq: false,
+                        }
+                    )],
+                    e(App(e(Id("x".to_string())), vec![*e(Id("x".to_string()))]))
                 )),
+                vec![],
             ),
-            vec![],
-        );
+            Err(TypeError::Recursive { .. })
+        ));
 
         // x = x => x; 1 + x
-        type_check(
-            Val(
-                String::from("x"),
-                Type::Var {
-                    typ: Rc::new(RefCell::new(None)),
-                },
-                Box::new(Fun(
-                    "x".to_string(),
-                    Type::Num,
-                    Box::new(Id("x".to_string())),
+        assert!(matches!(
+            type_check(
+                *e(Val(
+                    String::from("x"),
+                    Type::Var {
+                        typ: Rc::new(RefCell::new(None)),
+                        eq: false,
+                    },
+                    e(Fun(
+                        vec![("x".to_string(), Type::Num)],
+                        e(Id("x".to_string()))
+                    )),
+                    e(Add(e(Num(1)), e(Id(String::from("x"))))),
                 )),
-                Box::new(Add(Box::new(Num(1)), Box::new(Id(String::from("x"))))),
+                vec![],
             ),
-            vec![],
-        );
+            Err(TypeError::Mismatch { .. })
+        ));
+
+        assert!(matches!(
+            type_check(*e(App(e(Num(1)), vec![*e(Num(2))])), vec![]),
+            Err(TypeError::Mismatch { .. })
+        ));
 
-        type_check(App(Box::new(Num(1)), Box::new(Num(2))), vec![]);
+        assert_eq!(
+            type_check(*e(Add(e(Num(1)), e(Id("y".to_string())))), vec![]),
+            Err(TypeError::FreeIdent {
+                name: "y".to_string(),
+                span: Span::default()
+            })
+        );
     }
 
     #[test]
-    #[should_panic]
     fn addition_type_check_invalid() {
         assert_eq!(
             type_check(
-                Add(
-                    Box::new(Add(Box::new(Num(1)), Box::new(Num(2)))),
-                    Box::new(Fun(String::from("x"), Type::Num, Box::new(Num(100))))
-                ),
+                *e(Add(
+                    e(Add(e(Num(1)), e(Num(2)))),
+                    e(Fun(vec![(String::from("x"), Type::Num)], e(Num(100))))
+                )),
                 vec![]
-            )
-            .to_string(),
-            "Num".to_string()
+            ),
+            Err(TypeError::Mismatch {
+                expected: Type::Num,
+                found: Type::Fun(vec![Type::Num], Box::new(Type::Num)),
+                span: Span::default(),
+                because: Some(Box::new(Because {
+                    what: "`+` expression",
+                    span: Span::default()
+                }))
+            })
         );
-        assert_eq!(
+        assert!(matches!(
             type_check(
-                Val(
+                *e(Val(
                     String::from("x"),
-                    Type::Fun(Box::new(Type::Num), Box::new(Type::Num)),
-                    Box::new(Num(6)),
-                    Box::new(Add(Box::new(Num(1)), Box::new(Id(String::from("x")))))
-                ),
+                    Type::Fun(vec![Type::Num], Box::new(Type::Num)),
+                    e(Num(6)),
+                    e(Add(e(Num(1)), e(Id(String::from("x")))))
+                )),
                 vec![]
-            )
-            .to_string(),
-            "Num".to_string()
-        );
+            ),
+            Err(TypeError::Mismatch { .. })
+        ));
     }
 }
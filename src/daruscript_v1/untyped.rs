@@ -1,102 +1,220 @@
+use super::builtin::Builtin;
+use super::span::Span;
+use super::stack;
 use core::fmt;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum Expr {
+#[derive(Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+// Spans are ignored, so that trees parsed from different sources can be compared.
+impl PartialEq for Expr {
+    fn eq(&self, other: &Expr) -> bool {
+        stack::grow(|| self.kind == other.kind)
+    }
+}
+
+// A long chain of operators nests the tree deeper than the derived implementations can recurse,
+// so cloning and dropping grow the stack like the passes over the tree.
+impl Clone for Expr {
+    fn clone(&self) -> Expr {
+        stack::grow(|| Expr::new(self.kind.clone(), self.span))
+    }
+}
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let kind = std::mem::replace(&mut self.kind, ExprKind::Num(0));
+        stack::grow(|| drop(kind));
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExprKind {
     Num(isize),
+    Float(f64),
+    Bool(bool),
+    Str(String),
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
+    // Remainder `l % r`, with the sign of `l`
+    Mod(Box<Expr>, Box<Expr>),
+    // Exponentiation `l ** r`
+    Pow(Box<Expr>, Box<Expr>),
+    // Floating-point arithmetic and comparisons, e.g. `l +. r` and `l <. r`
+    FAdd(Box<Expr>, Box<Expr>),
+    FSub(Box<Expr>, Box<Expr>),
+    FMul(Box<Expr>, Box<Expr>),
+    FDiv(Box<Expr>, Box<Expr>),
+    // Floating-point negation `-.x`
+    FNeg(Box<Expr>),
+    FLt(Box<Expr>, Box<Expr>),
+    FLe(Box<Expr>, Box<Expr>),
+    FGt(Box<Expr>, Box<Expr>),
+    FGe(Box<Expr>, Box<Expr>),
+    // String concatenation `l ++ r`
+    Concat(Box<Expr>, Box<Expr>),
+    // Comparisons
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+    Lt(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Gt(Box<Expr>, Box<Expr>),
+    Ge(Box<Expr>, Box<Expr>),
+    // Logical operators, where `And` and `Or` short-circuit
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    // `if (cond) { then } else { otherwise }`
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
     Val(String, Box<Expr>, Box<Expr>),
+    // `{def f = {n => ...}; body}`, where `f` is also bound in its own definition
+    Rec(String, Box<Expr>, Box<Expr>),
     Id(String),
-    // First-class functions
-    Fun(String, Box<Expr>),
-    // Apply first-class function
-    App(Box<Expr>, Box<Expr>),
+    // First-class functions `{x, y => body}`
+    Fun(Vec<String>, Box<Expr>),
+    // Apply first-class function to its arguments
+    App(Box<Expr>, Vec<Expr>),
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Expr {
+        Expr { kind, span }
+    }
+
+    /// Moves the kind out of the expression, which `Drop` does not allow otherwise.
+    pub fn into_kind(mut self) -> ExprKind {
+        std::mem::replace(&mut self.kind, ExprKind::Num(0))
+    }
 }
 
 pub type Env = Vec<(String, Value)>;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Num(isize),
-    Closure(String, Expr, Env),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Closure(Vec<String>, Expr, Env),
+    /// A closure bound by `def` to the name in its first field.
+    ///
+    /// The name is bound to the closure itself each time it is applied,
+    /// which ties the recursive knot without a cyclic environment.
+    RecClosure(String, Vec<String>, Expr, Env),
+    Builtin(Builtin),
 }
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Expr::Num(n) => write!(f, "Num({})", n),
-            Expr::Add(left, right) => write!(f, "Add({}, {})", left.to_string(), right.to_string()),
-            Expr::Sub(left, right) => write!(f, "Sub({}, {})", left.to_string(), right.to_string()),
-            Expr::Val(ident, expr, body) => write!(
-                f,
-                "Val(\"{}\", {}, {})",
-                ident.to_string(),
-                expr.to_string(),
-                body.to_string()
-            ),
-            Expr::Id(ident) => write!(f, "Id(\"{}\")", ident.to_string()),
-            Expr::Fun(param, body) => {
-                write!(f, "Fun(\"{}\", {})", param.to_string(), body.to_string())
+        stack::grow(|| self.fmt_kind(f))
+    }
+}
+
+impl Expr {
+    fn fmt_kind(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Num(n) => write!(f, "Num({})", n),
+            ExprKind::Float(x) => write!(f, "Float({:?})", x),
+            ExprKind::Bool(b) => write!(f, "Bool({})", b),
+            ExprKind::Str(s) => write!(f, "Str({:?})", s),
+            ExprKind::Add(left, right) => write!(f, "Add({}, {})", left, right),
+            ExprKind::Sub(left, right) => write!(f, "Sub({}, {})", left, right),
+            ExprKind::Val(ident, expr, body) => write!(f, "Val(\"{}\", {}, {})", ident, expr, body),
+            ExprKind::Rec(ident, expr, body) => write!(f, "Rec(\"{}\", {}, {})", ident, expr, body),
+            ExprKind::Id(ident) => write!(f, "Id(\"{}\")", ident),
+            ExprKind::Fun(params, body) => {
+                write!(f, "Fun(")?;
+                for param in params {
+                    write!(f, "\"{}\", ", param)?;
+                }
+                write!(f, "{})", body)
+            }
+            ExprKind::App(func, args) => {
+                write!(f, "App({}", func)?;
+                for arg in args {
+                    write!(f, ", {}", arg)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::Mul(left, right) => write!(f, "Mul({}, {})", left, right),
+            ExprKind::Div(left, right) => write!(f, "Div({}, {})", left, right),
+            ExprKind::Mod(left, right) => write!(f, "Mod({}, {})", left, right),
+            ExprKind::Pow(left, right) => write!(f, "Pow({}, {})", left, right),
+            ExprKind::FAdd(left, right) => write!(f, "FAdd({}, {})", left, right),
+            ExprKind::FSub(left, right) => write!(f, "FSub({}, {})", left, right),
+            ExprKind::FMul(left, right) => write!(f, "FMul({}, {})", left, right),
+            ExprKind::FDiv(left, right) => write!(f, "FDiv({}, {})", left, right),
+            ExprKind::FNeg(expr) => write!(f, "FNeg({})", expr),
+            ExprKind::FLt(left, right) => write!(f, "FLt({}, {})", left, right),
+            ExprKind::FLe(left, right) => write!(f, "FLe({}, {})", left, right),
+            ExprKind::FGt(left, right) => write!(f, "FGt({}, {})", left, right),
+            ExprKind::FGe(left, right) => write!(f, "FGe({}, {})", left, right),
+            ExprKind::Concat(left, right) => write!(f, "Concat({}, {})", left, right),
+            ExprKind::Eq(left, right) => write!(f, "Eq({}, {})", left, right),
+            ExprKind::Ne(left, right) => write!(f, "Ne({}, {})", left, right),
+            ExprKind::Lt(left, right) => write!(f, "Lt({}, {})", left, right),
+            ExprKind::Le(left, right) => write!(f, "Le({}, {})", left, right),
+            ExprKind::Gt(left, right) => write!(f, "Gt({}, {})", left, right),
+            ExprKind::Ge(left, right) => write!(f, "Ge({}, {})", left, right),
+            ExprKind::Not(expr) => write!(f, "Not({})", expr),
+            ExprKind::And(left, right) => write!(f, "And({}, {})", left, right),
+            ExprKind::Or(left, right) => write!(f, "Or({}, {})", left, right),
+            ExprKind::If(cond, then, otherwise) => {
+                write!(f, "If({}, {}, {})", cond, then, otherwise)
             }
-            Expr::App(func, arg) => write!(f, "App({}, {})", func.to_string(), arg.to_string()),
-            Expr::Mul(left, right) => write!(f, "Mul({}, {})", left.to_string(), right.to_string()),
-            Expr::Div(left, right) => write!(f, "Div({}, {})", left.to_string(), right.to_string()),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Expr::*;
+    use super::ExprKind::*;
+    use super::{Expr, ExprKind};
     use crate::daruscript_v1::gen_ast as gen_ast_typed;
-    
-    fn gen_ast(source: &str) -> super::Expr {
-        gen_ast_typed(source).type_erase()
+    use crate::daruscript_v1::span::Span;
+
+    fn gen_ast(source: &str) -> ExprKind {
+        gen_ast_typed(source).unwrap().type_erase().into_kind()
+    }
+
+    fn e(kind: ExprKind) -> Box<Expr> {
+        Box::new(Expr::new(kind, Span::default()))
     }
 
     #[test]
     fn addition_ast() {
-        assert_eq!(gen_ast("1+2"), Add(Box::new(Num(1)), Box::new(Num(2))));
+        assert_eq!(gen_ast("1+2"), Add(e(Num(1)), e(Num(2))));
         assert_eq!(
             gen_ast("1+2+3"),
-            Add(
-                Box::new(Add(Box::new(Num(1)), Box::new(Num(2)))),
-                Box::new(Num(3))
-            )
+            Add(e(Add(e(Num(1)), e(Num(2)))), e(Num(3)))
         );
         assert_eq!(
             gen_ast("1+(2+3)"),
-            Add(
-                Box::new(Num(1)),
-                Box::new(Add(Box::new(Num(2)), Box::new(Num(3))))
-            )
+            Add(e(Num(1)), e(Add(e(Num(2)), e(Num(3)))))
         );
-        assert_eq!(gen_ast("5-3"), Sub(Box::new(Num(5)), Box::new(Num(3))));
+        assert_eq!(gen_ast("5-3"), Sub(e(Num(5)), e(Num(3))));
         assert_eq!(
             gen_ast("{val x = 6; 1 + x}"),
             Val(
                 String::from("x"),
-                Box::new(Num(6)),
-                Box::new(Add(Box::new(Num(1)), Box::new(Id(String::from("x")))))
+                e(Num(6)),
+                e(Add(e(Num(1)), e(Id(String::from("x")))))
             )
         );
-        assert_eq!(gen_ast("4*2"), Mul(Box::new(Num(4)), Box::new(Num(2))));
-        assert_eq!(gen_ast("8/4"), Div(Box::new(Num(8)), Box::new(Num(4))));
+        assert_eq!(gen_ast("4*2"), Mul(e(Num(4)), e(Num(2))));
+        assert_eq!(gen_ast("8/4"), Div(e(Num(8)), e(Num(4))));
         assert_eq!(
             gen_ast("6*3+2"),
-            Add(
-                Box::new(Mul(Box::new(Num(6)), Box::new(Num(3)))),
-                Box::new(Num(2))
-            )
+            Add(e(Mul(e(Num(6)), e(Num(3)))), e(Num(2)))
         );
         assert_eq!(
             gen_ast("10/(5-3)"),
-            Div(
-                Box::new(Num(10)),
-                Box::new(Sub(Box::new(Num(5)), Box::new(Num(3))))
-            )
+            Div(e(Num(10)), e(Sub(e(Num(5)), e(Num(3)))))
         );
     }
 
@@ -104,11 +222,59 @@ mod tests {
     fn first_class_functions() {
         assert_eq!(
             gen_ast("{x => 100}"),
-            Fun(String::from("x"), Box::new(Num(100))),
+            Fun(vec![String::from("x")], e(Num(100))),
         );
         assert_eq!(
-            gen_ast("{x => x}({x => 100})(10)").to_string(),
+            gen_ast_typed("{x => x}({x => 100})(10)")
+                .unwrap()
+                .type_erase()
+                .to_string(),
             r#"App(App(Fun("x", Id("x")), Fun("x", Num(100))), Num(10))"#,
         );
     }
+
+    #[test]
+    fn spans() {
+        let ast = gen_ast_typed("1 + {x =>\n  x * 2}(30)")
+            .unwrap()
+            .type_erase();
+        assert_eq!(ast.span, Span::new(0, 22, 1, 1));
+        let Add(left, right) = ast.into_kind() else {
+            panic!("expected Add")
+        };
+        assert_eq!(left.span, Span::new(0, 1, 1, 1));
+        assert_eq!(right.span, Span::new(4, 22, 1, 5));
+        let App(func, args) = right.into_kind() else {
+            panic!("expected App")
+        };
+        assert_eq!(func.span, Span::new(4, 18, 1, 5));
+        assert_eq!(args[0].span, Span::new(19, 21, 2, 10));
+        let Fun(_, body) = func.into_kind() else {
+            panic!("expected Fun")
+        };
+        assert_eq!(body.span, Span::new(12, 17, 2, 3));
+    }
+
+    #[test]
+    fn boolean_ast() {
+        assert_eq!(gen_ast("true"), Bool(true));
+        assert_eq!(gen_ast("!false"), Not(e(Bool(false))));
+        assert_eq!(
+            gen_ast("1 + 2 < 4 == true"),
+            Eq(
+                e(Lt(e(Add(e(Num(1)), e(Num(2)))), e(Num(4)))),
+                e(Bool(true))
+            )
+        );
+        assert_eq!(
+            gen_ast("a or b and c != d"),
+            Or(
+                e(Id("a".to_string())),
+                e(And(
+                    e(Id("b".to_string())),
+                    e(Ne(e(Id("c".to_string())), e(Id("d".to_string()))))
+                ))
+            )
+        );
+    }
 }
@@ -1,11 +1,23 @@
 use clap::Parser;
-use daru_script::daruscript_v1::type_check;
-use std::io::{self, Write};
+use daru_script::daruscript_v1::{gen_input, is_incomplete, Error, Session, BUILTINS, KEYWORDS};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+use std::process;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    // file_path: Option<String>,
+    /// Script to run, or `-` to read it from stdin. Starts the REPL if omitted.
+    file_path: Option<String>,
+
     #[clap(short, long)]
     printast: bool,
 
@@ -14,38 +26,341 @@ struct Cli {
 }
 
 fn main() {
-    use daru_script::daruscript_v1::gen_ast;
-    use daru_script::daruscript_v1::run;
-
     let cli = Cli::parse();
 
-    // if let Some(file_path) = cli.file_path {
-    //     todo!()
-    // }
+    if let Some(file_path) = &cli.file_path {
+        process::exit(run_file(&cli, file_path));
+    }
 
     println!("daruscript_v1");
+    let mut session = Session::new();
+    let mut editor: Editor<ReplHelper, DefaultHistory> =
+        Editor::new().expect("failed to start the line editor");
+    editor.set_helper(Some(ReplHelper::default()));
+    let interactive = io::stdin().is_terminal();
+    // Only interactive input is kept, so that piped scripts do not fill the history.
+    let history = history_path().filter(|_| interactive);
+    if let Some(path) = &history {
+        // The history file does not exist before the first session.
+        let _ = editor.load_history(path);
+    }
 
     // REPL
     loop {
-        print!("> ");
-        io::stdout().flush().expect("flush failed");
-        let mut line = String::new();
-        io::stdin()
-            .read_line(&mut line)
-            .expect("Failed to read line");
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            // Ctrl-C discards the line being edited.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => panic!("Failed to read line: {}", error),
+        };
 
         let line = line.trim();
+        // An empty line ends an interactive session, but only separates the inputs of a piped script.
+        if line.is_empty() && !interactive {
+            continue;
+        }
         if line.is_empty() || ["quit()", "exit()", "やめてください"].contains(&line) {
             break;
         }
 
+        let mut source = line.to_string();
+        read_rest(&mut editor, &mut source);
+        let _ = editor.add_history_entry(source.as_str());
+        let line = source.as_str();
+
         println!("{:?}", line);
-        if cli.printast {
-            println!("{}", gen_ast(&line));
+        if let Some(command) = line.strip_prefix(':') {
+            if !run_command(&mut session, command) {
+                break;
+            }
+            update_names(&mut editor, &session);
+            continue;
+        }
+
+        let output = if cli.printast {
+            print_ast(line)
         } else if cli.typecheck {
-            println!("{}", type_check(&line));
+            session.type_check(line).map(|typ| typ.to_string())
         } else {
-            println!("{}", run(&line));
+            session.run(line).map(|value| value.to_string())
+        };
+        match output {
+            Ok(output) => println!("{}", output),
+            Err(error) => report(&error, line),
         }
+        update_names(&mut editor, &session);
+    }
+
+    if let Some(path) = &history {
+        if let Err(error) = editor.save_history(path) {
+            eprintln!(
+                "Could not save the history to '{}': {}",
+                path.display(),
+                error
+            );
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".daru_script_history"))
+}
+
+/// Reads more lines into `source` while it is incomplete, until the end of the input.
+///
+/// Blank lines are skipped, so they do not end the input.
+fn read_rest(editor: &mut Editor<ReplHelper, DefaultHistory>, source: &mut String) {
+    while script_part(source).is_some_and(is_incomplete) {
+        let line = match editor.readline("... ") {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        let line = line.trim_end();
+        if line.trim_start().is_empty() {
+            continue;
+        }
+        source.push('\n');
+        source.push_str(line);
+    }
+}
+
+/// The DaruScript in a REPL input: the argument of `:type` and `:ast`,
+/// nothing for the other meta-commands and the whole input otherwise.
+fn script_part(source: &str) -> Option<&str> {
+    match source.strip_prefix(':') {
+        Some(command) => match command.split_once(char::is_whitespace) {
+            Some(("type" | "t" | "ast", arg)) => Some(arg),
+            _ => None,
+        },
+        None => Some(source),
+    }
+}
+
+fn update_names(editor: &mut Editor<ReplHelper, DefaultHistory>, session: &Session) {
+    if let Some(helper) = editor.helper_mut() {
+        helper.names = session
+            .bindings()
+            .map(|(name, _)| name.to_string())
+            .collect();
+    }
+}
+
+/// Completes keywords, builtins, the names declared in the session and meta-commands.
+#[derive(Default)]
+struct ReplHelper {
+    names: Vec<String>,
+}
+
+impl ReplHelper {
+    /// Returns where the word before `pos` starts and the ways to complete it.
+    fn completions(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        if before.starts_with(':') && !before.contains(char::is_whitespace) {
+            let commands = COMMANDS
+                .iter()
+                .filter(|command| command.starts_with(before));
+            return (0, commands.map(|command| command.to_string()).collect());
+        }
+
+        let start = match before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+        {
+            Some((i, c)) => i + c.len_utf8(),
+            None => 0,
+        };
+        let word = &before[start..];
+        if word.is_empty() {
+            return (pos, vec![]);
+        }
+
+        let keywords = KEYWORDS.iter().map(|(keyword, _)| *keyword);
+        let builtins = BUILTINS.iter().map(|builtin| builtin.name());
+        let mut candidates: Vec<String> = keywords
+            .chain(builtins)
+            .chain(self.names.iter().map(String::as_str))
+            .filter(|candidate| candidate.starts_with(word))
+            .map(str::to_string)
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        (start, candidates)
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.completions(line, pos))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+const COMMANDS: &[&str] = &[":type", ":ast", ":load", ":env", ":reset", ":help", ":quit"];
+
+const HELP: &str = "\
+:type <expr>   Show the type of an expression
+:ast <input>   Show the syntax trees of an input
+:load <file>   Run a script, keeping its declarations
+:env           List the declared names and their types
+:reset         Forget all declarations
+:help          Show this help
+:quit          Exit the REPL";
+
+/// Runs a REPL meta-command, given without its `:`, and returns whether the REPL should go on.
+fn run_command(session: &mut Session, command: &str) -> bool {
+    let (name, arg) = match command.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (command, ""),
+    };
+    // The source that errors are reported against.
+    let mut source = arg.to_string();
+    let output = match name {
+        "type" | "t" => session.type_check(arg).map(|typ| typ.to_string()),
+        "ast" => print_ast(arg),
+        "load" | "l" => match fs::read_to_string(arg) {
+            Ok(script) => {
+                source = script;
+                session.run(&source).map(|value| value.to_string())
+            }
+            Err(error) => {
+                eprintln!("Could not read '{}': {}", arg, error);
+                return true;
+            }
+        },
+        "env" => {
+            for (name, typ) in session.bindings() {
+                println!("{}: {}", name, typ);
+            }
+            return true;
+        }
+        "reset" => {
+            *session = Session::new();
+            return true;
+        }
+        "help" | "h" => Ok(HELP.to_string()),
+        "quit" | "q" => return false,
+        _ => {
+            eprintln!(
+                "Unknown command ':{}'. Type :help for a list of commands.",
+                name
+            );
+            return true;
+        }
+    };
+    match output {
+        Ok(output) => println!("{}", output),
+        Err(error) => report(&error, &source),
+    }
+    true
+}
+
+/// Runs a whole script and returns the exit status.
+///
+/// The statuses follow sysexits.h: 65 for an invalid program, 70 for a runtime error
+/// and 74 for a file that cannot be read.
+fn run_file(cli: &Cli, file_path: &str) -> i32 {
+    let source = if file_path == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source).map(|_| source)
+    } else {
+        fs::read_to_string(file_path)
+    };
+    let source = match source {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Could not read '{}': {}", file_path, error);
+            return 74;
+        }
+    };
+
+    let output = if cli.printast {
+        print_ast(&source)
+    } else if cli.typecheck {
+        Session::new()
+            .type_check(&source)
+            .map(|typ| typ.to_string())
+    } else {
+        Session::new().run(&source).map(|value| value.to_string())
+    };
+    match output {
+        Ok(output) => {
+            println!("{}", output);
+            0
+        }
+        Err(error) => {
+            report(&error, &source);
+            match error {
+                Error::Runtime(_) => 70,
+                _ => 65,
+            }
+        }
+    }
+}
+
+/// The syntax trees of the inputs in `source`, one per line.
+fn print_ast(source: &str) -> Result<String, Error> {
+    let inputs = gen_input(source)?;
+    let asts: Vec<String> = inputs.iter().map(ToString::to_string).collect();
+    Ok(asts.join("\n"))
+}
+
+/// Prints `error` to stderr with the lines of `source` it was found in.
+///
+/// The output is coloured when stderr is a terminal, unless `NO_COLOR` is set.
+fn report(error: &Error, source: &str) {
+    let color = io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    eprintln!("{}", error.render(source, color));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplHelper;
+
+    #[test]
+    fn completions() {
+        let helper = ReplHelper {
+            names: vec!["value".to_string(), "fact".to_string()],
+        };
+        assert_eq!(
+            helper.completions("{va", 3),
+            (
+                1,
+                vec!["val".to_string(), "value".to_string(), "var".to_string()]
+            )
+        );
+        assert_eq!(
+            helper.completions("1 + fa", 6),
+            (4, vec!["fact".to_string(), "false".to_string()])
+        );
+        assert_eq!(
+            helper.completions("fact(d) + 1", 6),
+            (5, vec!["def".to_string()])
+        );
+        assert_eq!(helper.completions("1 + ", 4), (4, vec![]));
+        assert_eq!(helper.completions(":t", 2), (0, vec![":type".to_string()]));
+        assert_eq!(
+            helper.completions(":type tr", 8),
+            (6, vec!["true".to_string(), "truncate".to_string()])
+        );
     }
 }
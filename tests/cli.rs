@@ -0,0 +1,238 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn daru_script(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_daru_script"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start daru_script");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+// The output of the REPL without its banner and the echoes of `inputs`, which are printed quoted.
+// Prompts are not printed since stdin is not a terminal.
+fn repl_results<'a>(stdout: &'a str, inputs: &[&str]) -> Vec<&'a str> {
+    let echoes: Vec<String> = inputs.iter().map(|input| format!("{:?}", input)).collect();
+    stdout
+        .lines()
+        .filter(|line| *line != "daruscript_v1" && !echoes.iter().any(|echo| line == echo))
+        .collect()
+}
+
+fn script(name: &str, source: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn run_file() {
+    let path = script(
+        "fact.daru",
+        "{def fact = {n =>\n  if (n == 0) { 1 } else { n * fact(n - 1) }\n};\nfact(5)}\n",
+    );
+    let output = daru_script(&[path.to_str().unwrap()], "");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "120\n");
+
+    let output = daru_script(&["--typecheck", path.to_str().unwrap()], "");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Num\n");
+}
+
+#[test]
+fn run_stdin() {
+    let output = daru_script(&["-"], "{val x = 20;\n x + 1}");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "21\n");
+
+    let output = daru_script(&["--printast", "-"], "val x = 1; def f = {n => n}; f(x)");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Val(\"x\", Num(1))\nRec(\"f\", Fun(\"n\", Id(\"n\")))\nApp(Id(\"f\"), Id(\"x\"))\n"
+    );
+}
+
+#[test]
+fn errors() {
+    let output = daru_script(&["-"], "1 +\n  true");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "\
+Type error: Expected Num, found Bool.
+ --> line 2:3
+  |
+1 | 1 +
+  | --- expected Num because of this `+` expression
+2 |   true
+  |   ^^^^ found Bool
+"
+    );
+
+    let output = daru_script(&["-"], "(1 +");
+    assert_eq!(output.status.code(), Some(65));
+
+    let output = daru_script(&["-"], "1 / 0");
+    assert_eq!(output.status.code(), Some(70));
+    assert!(output.stdout.is_empty());
+
+    let output = daru_script(&["does/not/exist.daru"], "");
+    assert_eq!(output.status.code(), Some(74));
+}
+
+#[test]
+fn deep_recursion() {
+    let output = daru_script(
+        &["-"],
+        "{def f = {n => if (n == 0) {0} else {f(n-1)}}; f(100000)}",
+    );
+    assert_eq!(output.status.code(), Some(70));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Runtime error: Stack overflow"));
+
+    let output = daru_script(
+        &["-"],
+        "{def f = {n => if (n == 0) {0} else {1 + f(n-1)}}; f(10000)}",
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "10000\n");
+
+    let output = daru_script(&["-"], &vec!["1"; 5000].join("+"));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "5000\n");
+
+    let output = daru_script(
+        &["-"],
+        &format!("{}1{}", "(".repeat(5000), ")".repeat(5000)),
+    );
+    assert_eq!(output.status.code(), Some(65));
+}
+
+#[test]
+fn run_file_declarations() {
+    let path = script(
+        "declarations.daru",
+        "val x = 4;\ndef double = {n => n * 2};\ndouble(x)\n",
+    );
+    let output = daru_script(&[path.to_str().unwrap()], "");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "8\n");
+}
+
+#[test]
+fn repl_commands() {
+    let path = script(
+        "lib.daru",
+        "val one = 1; def count = {n => if (n == 0) { 0 } else { one + count(n - 1) }}",
+    );
+    let load = format!(":load {}", path.display());
+    let inputs = [
+        load.as_str(),
+        "count(3)",
+        ":type count",
+        ":env",
+        ":reset",
+        ":env",
+        ":ast 1 + 2",
+        ":ast val x = 1; x",
+        ":nope",
+        ":quit",
+    ];
+    let output = daru_script(&[], &format!("{}\n1 + 1\n", inputs.join("\n")));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let results = repl_results(&stdout, &inputs);
+    // Loading prints the value of the last declaration.
+    assert_eq!(
+        results[0],
+        "RecClosure(count, n, If(Eq(Id(\"n\"), Num(0)), Num(0), Add(Id(\"one\"), App(Id(\"count\"), Sub(Id(\"n\"), Num(1))))))"
+    );
+    assert_eq!(
+        results[1..],
+        [
+            "3",
+            "Num -> Num",
+            "one: Num",
+            "count: Num -> Num",
+            "Add(Num(1), Num(2))",
+            "Val(\"x\", Num(1))",
+            "Id(\"x\")",
+        ]
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Unknown command ':nope'. Type :help for a list of commands.\n"
+    );
+}
+
+#[test]
+fn repl_multi_line() {
+    let inputs = [
+        "{val x = 3;\n  x *\n  2}",
+        "val f = {n =>\n  n + 1\n}",
+        "f(1)",
+        ":type {val z = 1;\n  z}",
+        ":ast {a =>\n  a}",
+        "{val y = 1;\n  y + 2}",
+        "\"multi\" ++\n  \"line\"",
+        "{val z = 1;",
+    ];
+    // Blank lines neither end an incomplete input nor the session when the input is piped.
+    let input = inputs
+        .join("\n")
+        .replace("1;\n  y", "1;\n\n  y")
+        .replace("f(1)", "\nf(1)\n");
+    let output = daru_script(&[], &input);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let results = repl_results(&stdout, &inputs);
+    assert_eq!(
+        results,
+        [
+            "6",
+            "Closure(n, Add(Id(\"n\"), Num(1)))",
+            "2",
+            "Num",
+            "Fun(\"a\", Id(\"a\"))",
+            "3",
+            "\"multiline\"",
+        ]
+    );
+    // The end of the input ends an incomplete input.
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "\
+Error at end: Expected expression.
+ --> line 1:12
+  |
+1 | {val z = 1;
+  |            ^ expected expression
+"
+    );
+}
+
+#[test]
+fn repl_unterminated_comment() {
+    let input = "1 /* a\n b */ + 2\n3 /* never closed\n\n";
+    let output = daru_script(&[], input);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        repl_results(&stdout, &["1 /* a\n b */ + 2", "3 /* never closed"]),
+        ["3"]
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "\
+Error: Unterminated block comment.
+ --> line 1:3
+  |
+1 | 3 /* never closed
+  |   ^^ comment starts here
+"
+    );
+}
@@ -1,34 +1,734 @@
-use daru_script::daruscript_v1::run;
+use daru_script::daruscript_v1::{
+    is_incomplete, run, type_check, Error, RuntimeError, Session, TypeError,
+};
 
 #[test]
 fn addition() {
-    assert_eq!(run("(3-1)+2").to_string(), "4");
-    assert_eq!(run("3-1+2").to_string(), "4");
-    assert_eq!(run("3-(1+2)").to_string(), "0");
-    assert_eq!(run("{val x = 3; x + 1}").to_string(), "4");
+    assert_eq!(run("(3-1)+2").unwrap().to_string(), "4");
+    assert_eq!(run("3-1+2").unwrap().to_string(), "4");
+    assert_eq!(run("3-(1+2)").unwrap().to_string(), "0");
+    assert_eq!(run("{val x = 3; x + 1}").unwrap().to_string(), "4");
 }
 
 #[test]
 fn multiplication() {
-    assert_eq!(run("3*2").to_string(), "6");
-    assert_eq!(run("3*2+1").to_string(), "7");
-    assert_eq!(run("3*(2+1)").to_string(), "9");
-    assert_eq!(run("{val x = 3; x * 2}").to_string(), "6");
+    assert_eq!(run("3*2").unwrap().to_string(), "6");
+    assert_eq!(run("3*2+1").unwrap().to_string(), "7");
+    assert_eq!(run("3*(2+1)").unwrap().to_string(), "9");
+    assert_eq!(run("{val x = 3; x * 2}").unwrap().to_string(), "6");
 }
 
 #[test]
 fn division() {
-    assert_eq!(run("6/2").to_string(), "3");
-    assert_eq!(run("6/2+1").to_string(), "4");
-    assert_eq!(run("6/(2+1)").to_string(), "2");
-    assert_eq!(run("{val x = 6; x / 2}").to_string(), "3");
+    assert_eq!(run("6/2").unwrap().to_string(), "3");
+    assert_eq!(run("6/2+1").unwrap().to_string(), "4");
+    assert_eq!(run("6/(2+1)").unwrap().to_string(), "2");
+    assert_eq!(run("{val x = 6; x / 2}").unwrap().to_string(), "3");
 }
 
 #[test]
 fn closure() {
-    assert_eq!(run("{x => x}(26) + {x => x}(4)").to_string(), "30");
-    assert_eq!(run("{x => {y => x + y} }(3)(5)").to_string(), "8");
-    assert_eq!(run("{t => t}(26) * {k => k}(6)").to_string(), "156");
-    assert_eq!(run("{v => v}(24) / {z => z}(3)").to_string(), "8");
-    assert_eq!(run("{l => l}({m => 2*m})(10) + 2").to_string(), "22");
-}
\ No newline at end of file
+    assert_eq!(run("{x => x}(26) + {x => x}(4)").unwrap().to_string(), "30");
+    assert_eq!(run("{x => {y => x + y} }(3)(5)").unwrap().to_string(), "8");
+    assert_eq!(
+        run("{t => t}(26) * {k => k}(6)").unwrap().to_string(),
+        "156"
+    );
+    assert_eq!(run("{v => v}(24) / {z => z}(3)").unwrap().to_string(), "8");
+    assert_eq!(
+        run("{l => l}({m => 2*m})(10) + 2").unwrap().to_string(),
+        "22"
+    );
+}
+
+#[test]
+fn syntax_errors() {
+    assert!(matches!(run("3 +"), Err(Error::Parse(_))));
+    assert!(matches!(run("{val x = ; x}"), Err(Error::Parse(_))));
+    assert!(matches!(run("3 @ 1"), Err(Error::Lex(_))));
+}
+
+#[test]
+fn type_errors() {
+    assert!(matches!(run("1 + {x => x}"), Err(Error::Type(_))));
+    assert!(matches!(run("{x => x(x)}"), Err(Error::Type(_))));
+    assert!(matches!(run("3(4)"), Err(Error::Type(_))));
+    assert!(matches!(type_check("y + 1"), Err(Error::Type(_))));
+    assert_eq!(
+        type_check("{f => f(1) + 1}({x => x})").unwrap().to_string(),
+        "Num"
+    );
+}
+
+// Tests run on threads with small stacks, which deep programs must not overflow.
+#[test]
+fn deep_programs() {
+    assert_eq!(
+        run("{def f = {n => if (n == 0) {0} else {1 + f(n - 1)}}; f(5000)}")
+            .unwrap()
+            .to_string(),
+        "5000"
+    );
+    let funs = format!("{}x{}", "{x => ".repeat(250), "}".repeat(250));
+    assert!(type_check(&funs).is_ok());
+}
+
+#[test]
+fn runtime_errors() {
+    assert!(matches!(
+        run("1 / (2 - 2)"),
+        Err(Error::Runtime(RuntimeError::DivisionByZero { .. }))
+    ));
+    assert!(matches!(
+        run("{val f = {x => 10 / x}; f(0)}"),
+        Err(Error::Runtime(RuntimeError::DivisionByZero { .. }))
+    ));
+    assert!(matches!(
+        run("9223372036854775807 + 1"),
+        Err(Error::Runtime(RuntimeError::Overflow { .. }))
+    ));
+    assert!(matches!(
+        run("-9223372036854775807 - 2"),
+        Err(Error::Runtime(RuntimeError::Overflow { .. }))
+    ));
+    assert!(matches!(
+        run("-9223372036854775808 - 1"),
+        Err(Error::Runtime(RuntimeError::Overflow { .. }))
+    ));
+    assert!(matches!(
+        run("{val x = 4611686018427387904; x * 2}"),
+        Err(Error::Runtime(RuntimeError::Overflow { .. }))
+    ));
+}
+
+#[test]
+fn let_polymorphism() {
+    assert_eq!(
+        run("{val id = {x => x}; {val a = id(1); id({y => y})(a)}}")
+            .unwrap()
+            .to_string(),
+        "1"
+    );
+    assert_eq!(
+        type_check("{val id = {x => x}; {val a = id(1); id({y => y})}}")
+            .unwrap()
+            .to_string(),
+        "'a -> 'a"
+    );
+    assert_eq!(
+        run("{val twice = {f => {x => f(f(x))}}; twice({n => n * 2})(twice({g => g})(5))}")
+            .unwrap()
+            .to_string(),
+        "20"
+    );
+    // Parameters stay monomorphic.
+    assert!(matches!(
+        type_check("{f => {val a = f(1); f({y => y})}}"),
+        Err(Error::Type(_))
+    ));
+    // Applications are not generalized (the value restriction).
+    assert!(matches!(
+        type_check("{val id = {g => g}({x => x}); {val a = id(1); id({y => y})}}"),
+        Err(Error::Type(_))
+    ));
+}
+
+#[test]
+fn booleans() {
+    assert_eq!(run("true").unwrap().to_string(), "true");
+    assert_eq!(run("!true").unwrap().to_string(), "false");
+    assert_eq!(run("1 + 2 == 3").unwrap().to_string(), "true");
+    assert_eq!(run("1 != 1").unwrap().to_string(), "false");
+    assert_eq!(run("2 < 3").unwrap().to_string(), "true");
+    assert_eq!(run("3 <= 3").unwrap().to_string(), "true");
+    assert_eq!(run("2 > 3").unwrap().to_string(), "false");
+    assert_eq!(run("2 >= 3").unwrap().to_string(), "false");
+    assert_eq!(run("true == !false").unwrap().to_string(), "true");
+    assert_eq!(run("1 < 2 and 2 < 3").unwrap().to_string(), "true");
+    assert_eq!(run("1 > 2 or 2 > 3").unwrap().to_string(), "false");
+    assert_eq!(
+        run("{val f = {x => x > 0 and !(x > 10)}; f(5)}")
+            .unwrap()
+            .to_string(),
+        "true"
+    );
+    assert_eq!(
+        type_check("{x => x == 1}").unwrap().to_string(),
+        "Num -> Bool"
+    );
+}
+
+#[test]
+fn short_circuit() {
+    // The right operand would fail with a division by zero if it were evaluated.
+    assert_eq!(run("false and 1 / 0 == 1").unwrap().to_string(), "false");
+    assert_eq!(run("true or 1 / 0 == 1").unwrap().to_string(), "true");
+    assert!(matches!(
+        run("true and 1 / 0 == 1"),
+        Err(Error::Runtime(RuntimeError::DivisionByZero { .. }))
+    ));
+}
+
+#[test]
+fn boolean_type_errors() {
+    assert!(matches!(run("1 + true"), Err(Error::Type(_))));
+    assert!(matches!(run("!1"), Err(Error::Type(_))));
+    assert!(matches!(run("1 and true"), Err(Error::Type(_))));
+    assert!(matches!(run("true < false"), Err(Error::Type(_))));
+    assert!(matches!(run("1 == true"), Err(Error::Type(_))));
+    assert!(matches!(
+        run("{x => x} == {x => x}"),
+        Err(Error::Type(TypeError::NotComparable { .. }))
+    ));
+    assert!(matches!(
+        run("{val f = {x => x}; f == f}"),
+        Err(Error::Type(TypeError::NotComparable { .. }))
+    ));
+    assert!(matches!(
+        run("{val eq = {x, y => x == y}; eq({x => x}, {x => x})}"),
+        Err(Error::Type(TypeError::NotComparable { .. }))
+    ));
+    assert_eq!(
+        type_check("{val eq = {x, y => x == y}; eq}")
+            .unwrap()
+            .to_string(),
+        "(''a, ''a) -> Bool"
+    );
+    assert_eq!(
+        run("{val eq = {x, y => x == y}; eq(1, 1) and eq(true, false)}")
+            .unwrap()
+            .to_string(),
+        "false"
+    );
+}
+
+#[test]
+fn if_else() {
+    assert_eq!(
+        run("if (1 < 2) { 10 } else { 20 }").unwrap().to_string(),
+        "10"
+    );
+    assert_eq!(
+        run("if (1 > 2) { 10 } else { 20 }").unwrap().to_string(),
+        "20"
+    );
+    assert_eq!(
+        run("{val sign = {n => if (n < 0) { 0 - 1 } else if (n == 0) { 0 } else { 1 }}; sign(0 - 5) + sign(0) * 10 + sign(7) * 100}")
+            .unwrap()
+            .to_string(),
+        "99"
+    );
+    assert_eq!(
+        type_check("{b => if (b) { {x => x} } else { {y => y + 1} }}")
+            .unwrap()
+            .to_string(),
+        "Bool -> Num -> Num"
+    );
+    // Only the taken branch is evaluated.
+    assert_eq!(
+        run("if (true) { 1 } else { 1 / 0 }").unwrap().to_string(),
+        "1"
+    );
+    assert_eq!(
+        run("if (false) { 1 / 0 } else { 2 }").unwrap().to_string(),
+        "2"
+    );
+}
+
+#[test]
+fn if_else_errors() {
+    assert!(matches!(
+        run("if (1) { 2 } else { 3 }"),
+        Err(Error::Type(_))
+    ));
+    assert!(matches!(
+        run("if (true) { 2 } else { false }"),
+        Err(Error::Type(_))
+    ));
+    assert!(matches!(run("if (true) { 2 }"), Err(Error::Parse(_))));
+    assert!(matches!(
+        run("if true { 2 } else { 3 }"),
+        Err(Error::Parse(_))
+    ));
+}
+
+#[test]
+fn recursion() {
+    assert_eq!(
+        run("{def fact = {n => if (n == 0) { 1 } else { n * fact(n - 1) }}; fact(10)}")
+            .unwrap()
+            .to_string(),
+        "3628800"
+    );
+    assert_eq!(
+        run("{def fib = {n => if (n < 2) { n } else { fib(n - 1) + fib(n - 2) }}; fib(15)}")
+            .unwrap()
+            .to_string(),
+        "610"
+    );
+    // A recursive closure keeps the environment it was defined in.
+    assert_eq!(
+        run("{val k = 3; {def mul = {n => if (n == 0) { 0 } else { k + mul(n - 1) }}; mul(4)}}")
+            .unwrap()
+            .to_string(),
+        "12"
+    );
+    assert_eq!(
+        type_check("{def loop = {n => loop(n)}; {val a = loop(1); loop(true)}}")
+            .unwrap()
+            .to_string(),
+        "'a"
+    );
+    assert!(matches!(run("{def f = {n => f}; f}"), Err(Error::Type(_))));
+    assert!(matches!(run("{def x = 1; x}"), Err(Error::Parse(_))));
+    assert!(matches!(
+        run("{val f = {n => f(n)}; f}"),
+        Err(Error::Type(_))
+    ));
+}
+
+#[test]
+fn multiple_parameters() {
+    assert_eq!(run("{x, y => x - y}(10, 3)").unwrap().to_string(), "7");
+    assert_eq!(
+        run("{val add3 = {x, y, z => x + y + z}; add3(1, 2, 3) * 2}")
+            .unwrap()
+            .to_string(),
+        "12"
+    );
+    assert_eq!(
+        run("{def pow = {b, n => if (n == 0) { 1 } else { b * pow(b, n - 1) }}; pow(2, 10)}")
+            .unwrap()
+            .to_string(),
+        "1024"
+    );
+    assert_eq!(
+        type_check("{f, x => f(x, x)}").unwrap().to_string(),
+        "(('a, 'a) -> 'b, 'a) -> 'b"
+    );
+    assert_eq!(
+        type_check("{x, y => x < y}").unwrap().to_string(),
+        "(Num, Num) -> Bool"
+    );
+    assert!(matches!(
+        type_check("{x, y => x + y}(1)"),
+        Err(Error::Type(TypeError::Arity {
+            expected: 2,
+            found: 1,
+            ..
+        }))
+    ));
+    assert!(matches!(
+        type_check("{f => f(1) + f(1, 2)}"),
+        Err(Error::Type(TypeError::Arity { .. }))
+    ));
+    assert!(matches!(
+        type_check("{val g = {f => f(1)}; g({x, y => x})}"),
+        Err(Error::Type(TypeError::Mismatch { .. }))
+    ));
+    assert!(matches!(run("{x, => x}"), Err(Error::Parse(_))));
+    assert!(matches!(run("{x => x}(1,)"), Err(Error::Parse(_))));
+}
+
+#[test]
+fn type_annotations() {
+    assert_eq!(run("{val x: Num = 3; x + 1}").unwrap().to_string(), "4");
+    assert_eq!(
+        type_check("{(f: Num -> Num) => f(1)}").unwrap().to_string(),
+        "(Num -> Num) -> Num"
+    );
+    assert_eq!(
+        type_check("{x: Bool, y => if (x) { y } else { 0 }}")
+            .unwrap()
+            .to_string(),
+        "(Bool, Num) -> Num"
+    );
+    assert_eq!(
+        type_check("{val add: (Num, Num) -> Num = {x, y => x + y}; add}")
+            .unwrap()
+            .to_string(),
+        "(Num, Num) -> Num"
+    );
+    assert_eq!(
+        type_check(
+            "{def fact: Num -> Num = {n => if (n == 0) { 1 } else { n * fact(n - 1) }}; fact}"
+        )
+        .unwrap()
+        .to_string(),
+        "Num -> Num"
+    );
+    // An annotation makes a polymorphic function monomorphic.
+    assert_eq!(
+        type_check("({x => x} : Bool -> Bool)").unwrap().to_string(),
+        "Bool -> Bool"
+    );
+    assert_eq!(run("(1 + 2 : Num) * 2").unwrap().to_string(), "6");
+    assert!(matches!(
+        type_check("{val id: Num -> Num = {x => x}; id(true)}"),
+        Err(Error::Type(TypeError::Mismatch { .. }))
+    ));
+    assert!(matches!(
+        run("{val x: Bool = 3; x}"),
+        Err(Error::Type(TypeError::Mismatch { .. }))
+    ));
+    assert!(matches!(
+        run("{(x: Num) => x}(true)"),
+        Err(Error::Type(TypeError::Mismatch { .. }))
+    ));
+    assert!(matches!(run("(1 : Bool)"), Err(Error::Type(_))));
+    assert!(matches!(run("{val x: = 3; x}"), Err(Error::Parse(_))));
+}
+
+#[test]
+fn type_error_messages() {
+    assert_eq!(
+        run("{f => f(1)}({x, y => x})").unwrap_err().to_string(),
+        "[line 1:13] Type error: Expected Num -> 'a, found ('b, 'c) -> 'b."
+    );
+}
+
+#[test]
+fn strings() {
+    assert_eq!(run(r#""hello""#).unwrap().to_string(), r#""hello""#);
+    assert_eq!(
+        run(r#"{val greet = {name => "Hello, " ++ name ++ "!"}; greet("Daru")}"#)
+            .unwrap()
+            .to_string(),
+        r#""Hello, Daru!""#
+    );
+    assert_eq!(
+        run(r#""tab\tquote\"\u{41}""#).unwrap().to_string(),
+        r#""tab\tquote\"A""#
+    );
+    assert_eq!(run(r#"length("日本語")"#).unwrap().to_string(), "3");
+    assert_eq!(run(r#"at("日本語", 1)"#).unwrap().to_string(), r#""本""#);
+    assert_eq!(run(r#""ab" ++ "c" == "abc""#).unwrap().to_string(), "true");
+    assert_eq!(type_check("length").unwrap().to_string(), "Str -> Num");
+    assert_eq!(type_check("at").unwrap().to_string(), "(Str, Num) -> Str");
+    assert_eq!(
+        type_check(r#"{s: Str => s ++ "!"}"#).unwrap().to_string(),
+        "Str -> Str"
+    );
+    // Bindings shadow the builtins.
+    assert_eq!(
+        run("{val length = 7; length + 1}").unwrap().to_string(),
+        "8"
+    );
+
+    assert!(matches!(run(r#""a" + 1"#), Err(Error::Type(_))));
+    assert!(matches!(run(r#"1 ++ "a""#), Err(Error::Type(_))));
+    assert!(matches!(run("length(1)"), Err(Error::Type(_))));
+    assert!(matches!(
+        run(r#"at("abc", 3)"#),
+        Err(Error::Runtime(RuntimeError::IndexOutOfBounds {
+            index: 3,
+            length: 3,
+            ..
+        }))
+    ));
+    assert!(matches!(
+        run(r#"at("abc", 0 - 1)"#),
+        Err(Error::Runtime(RuntimeError::IndexOutOfBounds { .. }))
+    ));
+    assert!(matches!(run(r#""a\qb""#), Err(Error::Lex(_))));
+}
+
+#[test]
+fn floats() {
+    assert_eq!(run("1.5 +. 2.25").unwrap().to_string(), "3.75");
+    assert_eq!(run("7.0 /. 2.0 *. 3.0").unwrap().to_string(), "10.5");
+    assert_eq!(run("1.0 -. -.0.5").unwrap().to_string(), "1.5");
+    assert_eq!(run("{val x = 2.5; -.x}").unwrap().to_string(), "-2.5");
+    assert_eq!(run("-.(1.5 *. 2.0)").unwrap().to_string(), "-3.0");
+    assert_eq!(run("-.0.0").unwrap().to_string(), "-0.0");
+    // `-` before a float literal makes a negative literal, but does not negate other floats.
+    assert_eq!(run("-1.5 *. 2.0").unwrap().to_string(), "-3.0");
+    assert_eq!(run("2.0 -. -0.5").unwrap().to_string(), "2.5");
+    assert!(matches!(run("{val x = 2.5; -x}"), Err(Error::Type(_))));
+    assert_eq!(
+        type_check("{x => -.x}").unwrap().to_string(),
+        "Float -> Float"
+    );
+    assert_eq!(run("1.0 /. 0.0").unwrap().to_string(), "inf");
+    assert_eq!(run("0.1 +. 0.2 >. 0.3").unwrap().to_string(), "true");
+    assert_eq!(
+        run("2.5 <=. 2.5 and 1.0 <. 2.0").unwrap().to_string(),
+        "true"
+    );
+    assert_eq!(run("1.5 == 1.5").unwrap().to_string(), "true");
+    assert_eq!(run("float(7) /. 2.0").unwrap().to_string(), "3.5");
+    assert_eq!(run("truncate(0.0 -. 3.9)").unwrap().to_string(), "-3");
+    assert_eq!(run("7 / 2").unwrap().to_string(), "3");
+    assert_eq!(
+        type_check("{x => x *. x}").unwrap().to_string(),
+        "Float -> Float"
+    );
+    assert_eq!(
+        type_check("{val half = {x: Float => x /. 2.0}; half}")
+            .unwrap()
+            .to_string(),
+        "Float -> Float"
+    );
+
+    // Integers and floats are not mixed implicitly.
+    assert!(matches!(run("1 + 1.5"), Err(Error::Type(_))));
+    assert!(matches!(run("1 +. 1.5"), Err(Error::Type(_))));
+    assert!(matches!(run("1.5 < 2.5"), Err(Error::Type(_))));
+    assert!(matches!(run("1.5 == 1"), Err(Error::Type(_))));
+    assert!(matches!(run("-.1"), Err(Error::Type(_))));
+    assert!(matches!(
+        run("truncate(1.0 /. 0.0)"),
+        Err(Error::Runtime(RuntimeError::Overflow { .. }))
+    ));
+}
+
+#[test]
+fn unicode_identifiers() {
+    assert_eq!(
+        run("{val 階乗 = 1; {def 事実 = {n => if (n == 0) { 階乗 } else { n * 事実(n - 1) }}; 事実(5)}}")
+            .unwrap()
+            .to_string(),
+        "120"
+    );
+    assert_eq!(
+        run("{val 하나 = 1; {val 둘 = 2; 하나 + 둘}}")
+            .unwrap()
+            .to_string(),
+        "3"
+    );
+    assert_eq!(
+        run(r#"length("안녕하세요") + length("やめてください")"#)
+            .unwrap()
+            .to_string(),
+        "12"
+    );
+    assert_eq!(
+        run("{val 数 = true; 数 + 1}").unwrap_err().to_string(),
+        "[line 1:16] Type error: Expected Num, found Bool."
+    );
+}
+
+#[test]
+fn number_literals() {
+    assert_eq!(
+        run("0xff + 0o17 + 0b101 + 1_000").unwrap().to_string(),
+        "1275"
+    );
+    assert_eq!(run("1.5e3 +. 2E-1").unwrap().to_string(), "1500.2");
+    assert!(matches!(run("99999999999999999999"), Err(Error::Lex(_))));
+    // The smallest integer can be written, but its magnitude alone does not fit.
+    assert_eq!(
+        run("-9223372036854775808").unwrap().to_string(),
+        "-9223372036854775808"
+    );
+    assert_eq!(
+        run("-9223372036854775808 + 9223372036854775807")
+            .unwrap()
+            .to_string(),
+        "-1"
+    );
+    for source in [
+        "9223372036854775808",
+        "1 - 9223372036854775808",
+        "-9223372036854775808 ** 1",
+        "-(9223372036854775808)",
+    ] {
+        assert!(matches!(run(source), Err(Error::Parse(_))), "{}", source);
+    }
+    // `e` only starts an exponent when digits follow.
+    assert!(matches!(run("{val e = 2; 3e}"), Err(Error::Parse(_))));
+}
+
+#[test]
+fn rendered_errors() {
+    let source = "if (true) {\n  1\n} else { false }";
+    assert_eq!(
+        run(source).unwrap_err().render(source, false),
+        "\
+Type error: Expected Num, found Bool.
+ --> line 3:8
+  |
+1 | if (true) {
+  |           - expected Num because of this branch
+3 | } else { false }
+  |        ^^^^^^^^^ found Bool"
+    );
+
+    let source = "1 @ 2 # 3";
+    assert_eq!(
+        run(source).unwrap_err().render(source, false),
+        "\
+Error: Unexpected character '@'.
+ --> line 1:3
+  |
+1 | 1 @ 2 # 3
+  |   ^ unexpected character
+
+Error: Unexpected character '#'.
+ --> line 1:7
+  |
+1 | 1 @ 2 # 3
+  |       ^ unexpected character"
+    );
+}
+
+#[test]
+fn shadowing() {
+    assert_eq!(run("{val x = 1; {val x = 2; x}}").unwrap().to_string(), "2");
+    assert_eq!(
+        run("{val x = 1; {val x = x + 1; x * 10}}")
+            .unwrap()
+            .to_string(),
+        "20"
+    );
+    assert_eq!(
+        run("{val x = true; {val x = 3; x + 1}}")
+            .unwrap()
+            .to_string(),
+        "4"
+    );
+    assert_eq!(run("{x => {x => x}}(1)(2)").unwrap().to_string(), "2");
+}
+
+#[test]
+fn session() {
+    let mut session = Session::new();
+    assert_eq!(session.run("val x = 3").unwrap().to_string(), "3");
+    assert_eq!(session.run("x + 1").unwrap().to_string(), "4");
+    assert!(session
+        .run("def fact = {n => if (n == 0) { 1 } else { n * fact(n - 1) }};")
+        .is_ok());
+    assert_eq!(session.run("fact(x)").unwrap().to_string(), "6");
+    assert_eq!(
+        session.type_check("fact").unwrap().to_string(),
+        "Num -> Num"
+    );
+
+    // Declarations are generalized.
+    assert!(session.run("val id = {y => y}").is_ok());
+    assert_eq!(session.run("id(id)(true)").unwrap().to_string(), "true");
+
+    // Redefining a name replaces it, but earlier functions keep the old binding.
+    assert!(session.run("val addx = {y => x + y}").is_ok());
+    assert_eq!(session.run("val x = false").unwrap().to_string(), "false");
+    assert_eq!(session.type_check("x").unwrap().to_string(), "Bool");
+    assert_eq!(session.run("addx(1)").unwrap().to_string(), "4");
+
+    // Failed declarations are not recorded.
+    assert!(matches!(session.run("val y = 1 + x"), Err(Error::Type(_))));
+    assert!(matches!(
+        session.run("val y = 1 / 0"),
+        Err(Error::Runtime(_))
+    ));
+    assert!(matches!(
+        session.run("y"),
+        Err(Error::Type(TypeError::FreeIdent { .. }))
+    ));
+    // Type checking does not record declarations either.
+    assert_eq!(session.type_check("val z = 1").unwrap().to_string(), "Num");
+    assert!(matches!(session.run("z"), Err(Error::Type(_))));
+
+    assert!(matches!(session.run("val w = 1 2"), Err(Error::Parse(_))));
+
+    // A declaration that is not generalized only gets its type from the inputs that succeed.
+    assert!(session.run("val g = {x => x}({y => y})").is_ok());
+    assert_eq!(session.type_check("g(1)").unwrap().to_string(), "Num");
+    assert!(matches!(session.run("g(1) + true"), Err(Error::Type(_))));
+    assert!(matches!(session.run("g(1) / 0"), Err(Error::Runtime(_))));
+    assert_eq!(session.type_check("g").unwrap().to_string(), "'a -> 'a");
+    assert_eq!(session.run("g(true)").unwrap().to_string(), "true");
+    assert_eq!(session.type_check("g").unwrap().to_string(), "Bool -> Bool");
+    assert!(matches!(session.run("g(1)"), Err(Error::Type(_))));
+}
+
+#[test]
+fn session_inputs() {
+    let mut session = Session::new();
+    assert_eq!(
+        session
+            .run("val x = 3; def fact = {n => if (n == 0) { 1 } else { n * fact(n - 1) }}; fact(x)")
+            .unwrap()
+            .to_string(),
+        "6"
+    );
+    assert_eq!(session.run("x; x + 1;").unwrap().to_string(), "4");
+    let names: Vec<_> = session.bindings().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["x", "fact"]);
+
+    // Inputs before an error stay recorded.
+    assert!(matches!(
+        session.run("val y = 1; val z = y / 0; val w = 2"),
+        Err(Error::Runtime(_))
+    ));
+    assert_eq!(session.run("y").unwrap().to_string(), "1");
+    assert!(session.run("z").is_err());
+    assert!(session.run("w").is_err());
+
+    // Declarations type checked together see each other.
+    assert_eq!(
+        session
+            .type_check("val f = {a => a}; f(true)")
+            .unwrap()
+            .to_string(),
+        "Bool"
+    );
+    assert!(session.type_check("f").is_err());
+
+    assert!(matches!(session.run("1 2; 3 +; }"), Err(Error::Parse(errors)) if errors.len() == 3));
+    assert!(matches!(session.run(";"), Err(Error::Parse(_))));
+    assert!(matches!(session.run(""), Err(Error::Parse(_))));
+}
+
+#[test]
+fn operators() {
+    assert_eq!(run("7 % 3").unwrap().to_string(), "1");
+    assert_eq!(run("-7 % 3").unwrap().to_string(), "-1");
+    assert_eq!(run("2 ** 10").unwrap().to_string(), "1024");
+    assert_eq!(run("2 ** 3 ** 2").unwrap().to_string(), "512");
+    assert_eq!(run("-2 ** 2").unwrap().to_string(), "-4");
+    assert_eq!(
+        run("{val double = {x => x * 2}; 3 + 1 |> double}")
+            .unwrap()
+            .to_string(),
+        "8"
+    );
+    assert!(matches!(
+        run("1 % 0"),
+        Err(Error::Runtime(RuntimeError::DivisionByZero { .. }))
+    ));
+    assert!(matches!(
+        run("2 ** -1"),
+        Err(Error::Runtime(RuntimeError::NegativeExponent { .. }))
+    ));
+    assert_eq!(run("1 ** 5000000000").unwrap().to_string(), "1");
+    assert_eq!(run("0 ** 5000000000").unwrap().to_string(), "0");
+    assert_eq!(run("0 ** 0").unwrap().to_string(), "1");
+    assert_eq!(run("(0 - 1) ** 5000000000").unwrap().to_string(), "1");
+    assert_eq!(run("(0 - 1) ** 5000000001").unwrap().to_string(), "-1");
+    assert!(matches!(
+        run("2 ** 63"),
+        Err(Error::Runtime(RuntimeError::Overflow { .. }))
+    ));
+    assert!(matches!(run("1.5 % 2.0"), Err(Error::Type(_))));
+    assert!(matches!(run("1 |> 2"), Err(Error::Type(_))));
+}
+
+#[test]
+fn incomplete_input() {
+    assert!(is_incomplete("{val x = 3;"));
+    assert!(is_incomplete("{x =>\n  x + "));
+    assert!(is_incomplete("f(1,"));
+    assert!(is_incomplete("if (true) { 1 }"));
+    assert!(is_incomplete("val f = {n =>"));
+    assert!(is_incomplete("1 + /* comment"));
+    assert!(is_incomplete("\"abc"));
+    assert!(is_incomplete("1 /* comment"));
+    assert!(is_incomplete("{x => /* a /* b */"));
+
+    assert!(!is_incomplete("{val x = 3; x}"));
+    assert!(!is_incomplete("val x = 3;"));
+    assert!(!is_incomplete("1 + 2)"));
+    assert!(!is_incomplete("{1 => 1"));
+    assert!(!is_incomplete("1 + true"));
+    assert!(!is_incomplete("@ + {"));
+}